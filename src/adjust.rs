@@ -0,0 +1,212 @@
+/// Tonal adjustments of the input image, applied in the order of the fields.
+///
+/// The alpha channel is left as it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adjustments {
+    /// Multiplies the color channels.
+    pub brightness: f32,
+    /// Scales the distance of the color channels from the middle gray.
+    pub contrast: f32,
+    /// Raises the color channels to the power of `1 / gamma`.
+    pub gamma: f32,
+    /// 0.0 makes the image grayscale. It has no effect on grayscale images.
+    pub saturation: f32,
+    /// Degrees to rotate the hue. It has no effect on grayscale images.
+    pub hue_rotation: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Adjustments {
+        Adjustments {
+            brightness: 1.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+            hue_rotation: 0.0,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    /// Adjusts an image with one (luma) or three (RGB) color channels optionally followed by alpha.
+    pub fn apply<P>(&self, image: &mut image::ImageBuffer<P, Vec<u8>>)
+    where
+        P: image::Pixel<Subpixel = u8> + 'static,
+    {
+        if self.is_identity() {
+            return;
+        }
+
+        let table = self.tone_table();
+        let matrix = self.color_matrix();
+        let rgb = P::CHANNEL_COUNT >= 3;
+
+        for pixel in image.pixels_mut() {
+            let channels = pixel.channels_mut();
+            if rgb {
+                let (r, g, b) = (
+                    table[channels[0] as usize],
+                    table[channels[1] as usize],
+                    table[channels[2] as usize],
+                );
+                for (channel, row) in channels.iter_mut().zip(&matrix) {
+                    let value = row[0] * r + row[1] * g + row[2] * b;
+                    *channel = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+                }
+            } else {
+                channels[0] = (table[channels[0] as usize] * 255.0).round() as u8;
+            }
+        }
+    }
+
+    /// Adjusts a decoded image of any color type.
+    pub fn apply_dynamic(&self, image: image::DynamicImage) -> image::DynamicImage {
+        use image::DynamicImage::*;
+
+        if self.is_identity() {
+            return image;
+        }
+        match image {
+            ImageLuma8(mut buffer) => {
+                self.apply(&mut buffer);
+                ImageLuma8(buffer)
+            }
+            ImageLumaA8(mut buffer) => {
+                self.apply(&mut buffer);
+                ImageLumaA8(buffer)
+            }
+            ImageRgb8(mut buffer) => {
+                self.apply(&mut buffer);
+                ImageRgb8(buffer)
+            }
+            image => {
+                let mut buffer = image.to_rgba();
+                self.apply(&mut buffer);
+                ImageRgba8(buffer)
+            }
+        }
+    }
+
+    /// Maps each channel value to the result of brightness, contrast and gamma from 0.0 to 1.0.
+    fn tone_table(&self) -> [f32; 256] {
+        let mut table = [0.0; 256];
+        for (value, entry) in table.iter_mut().enumerate() {
+            let v = value as f32 / 255.0 * self.brightness;
+            let v = ((v - 0.5) * self.contrast + 0.5).clamp(0.0, 1.0);
+            *entry = v.powf(1.0 / self.gamma);
+        }
+        table
+    }
+
+    /// Returns the saturation and hue rotation as a matrix of the filter effects specification.
+    fn color_matrix(&self) -> [[f32; 3]; 3] {
+        let s = self.saturation;
+        let saturate = [
+            [0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s],
+        ];
+
+        let (sin, cos) = self.hue_rotation.to_radians().sin_cos();
+        let hue_rotate = [
+            [
+                0.213 + cos * 0.787 - sin * 0.213,
+                0.715 - cos * 0.715 - sin * 0.715,
+                0.072 - cos * 0.072 + sin * 0.928,
+            ],
+            [
+                0.213 - cos * 0.213 + sin * 0.143,
+                0.715 + cos * 0.285 + sin * 0.140,
+                0.072 - cos * 0.072 - sin * 0.283,
+            ],
+            [
+                0.213 - cos * 0.213 - sin * 0.787,
+                0.715 - cos * 0.715 + sin * 0.715,
+                0.072 + cos * 0.928 + sin * 0.072,
+            ],
+        ];
+
+        let mut matrix = [[0.0; 3]; 3];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| hue_rotate[i][k] * saturate[k][j]).sum();
+            }
+        }
+        matrix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adjust(adjustments: Adjustments, pixel: image::Rgba<u8>) -> image::Rgba<u8> {
+        let mut image = image::RgbaImage::from_pixel(1, 1, pixel);
+        adjustments.apply(&mut image);
+        *image.get_pixel(0, 0)
+    }
+
+    #[test]
+    fn test_identity() {
+        let pixel = image::Rgba([12, 200, 99, 40]);
+        let adjustments = Adjustments {
+            hue_rotation: 360.0,
+            ..Adjustments::default()
+        };
+        assert_eq!(adjust(Adjustments::default(), pixel), pixel);
+        assert_eq!(adjust(adjustments, pixel), pixel);
+    }
+
+    #[test]
+    fn test_brightness_contrast() {
+        let pixel = image::Rgba([100, 100, 100, 128]);
+        let brighter = Adjustments {
+            brightness: 2.0,
+            ..Adjustments::default()
+        };
+        assert_eq!(adjust(brighter, pixel), image::Rgba([200, 200, 200, 128]));
+
+        let flat = Adjustments {
+            contrast: 0.0,
+            ..Adjustments::default()
+        };
+        assert_eq!(adjust(flat, pixel), image::Rgba([128, 128, 128, 128]));
+    }
+
+    #[test]
+    fn test_gamma_luma() {
+        let mut image = image::GrayAlphaImage::from_pixel(1, 1, image::LumaA([64, 255]));
+        let adjustments = Adjustments {
+            gamma: 2.0,
+            ..Adjustments::default()
+        };
+        adjustments.apply(&mut image);
+        assert_eq!(image.get_pixel(0, 0), &image::LumaA([128, 255]));
+    }
+
+    #[test]
+    fn test_saturation_hue() {
+        let red = image::Rgba([255, 0, 0, 255]);
+        let gray = adjust(
+            Adjustments {
+                saturation: 0.0,
+                ..Adjustments::default()
+            },
+            red,
+        );
+        assert!(gray[0] == gray[1] && gray[1] == gray[2]);
+
+        let rotated = adjust(
+            Adjustments {
+                hue_rotation: 120.0,
+                ..Adjustments::default()
+            },
+            red,
+        );
+        assert!(rotated[1] > rotated[0] && rotated[1] > rotated[2]);
+    }
+}
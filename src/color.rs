@@ -1,23 +1,339 @@
 #![allow(dead_code)]
 
+use failure::Fail;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Fail)]
+pub enum ColorParseError {
+    #[fail(
+        display = "invalid hex color: {} (expected #RGB, #RGBA, #RRGGBB or #RRGGBBAA)",
+        _0
+    )]
+    InvalidHex(String),
+    #[fail(display = "unknown color name: {}", _0)]
+    UnknownName(String),
+    #[fail(
+        display = "invalid color function: {} (expected rgb(R, G, B[, A]) or hsl(H, S%, L%[, A]))",
+        _0
+    )]
+    InvalidFunction(String),
+}
+
+/// CSS named colors as `0xRRGGBB`, sorted by name.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+/// Splits a comma separated list at commas outside of parentheses such as those of `rgb(0, 0, 0)`.
+pub fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0;
+    list.split(move |c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        c == ',' && depth == 0
+    })
+}
+
+/// Converts an sRGB channel to linear light from 0.0 to 1.0.
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let value = f32::from(value) / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts linear light from 0.0 to 1.0 to an sRGB channel.
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let value = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round() as u8
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color([u8; 4]);
 
-impl From<&str> for Color {
-    fn from(s: &str) -> Color {
-        let s = s.chars().skip(1).collect::<String>();
-        let code = if s.len() < 8 {
-            u32::from_str_radix(&s, 16).unwrap() << 8 | 0x00_00_00_ff
-        } else {
-            u32::from_str_radix(&s, 16).unwrap()
-        };
-        Color::from_code(code)
+impl FromStr for Color {
+    type Err = ColorParseError;
+
+    /// Parses `#RGB`, `#RGBA`, `#RRGGBB`, `#RRGGBBAA`, a CSS color name,
+    /// `rgb()`, `rgba()`, `hsl()` or `hsla()`.
+    fn from_str(s: &str) -> Result<Color, ColorParseError> {
+        let s = s.trim().to_lowercase();
+
+        if let Some(hex) = s.strip_prefix('#') {
+            return parse_hex(hex).ok_or_else(|| ColorParseError::InvalidHex(s.clone()));
+        }
+        if let Some(open) = s.find('(') {
+            return parse_function(&s[..open], &s[open + 1..])
+                .ok_or_else(|| ColorParseError::InvalidFunction(s.clone()));
+        }
+        if s == "transparent" {
+            return Ok(Color::clear());
+        }
+
+        NAMED_COLORS
+            .binary_search_by_key(&s.as_str(), |(name, _)| name)
+            .map(|index| Color::from_code(NAMED_COLORS[index].1 << 8 | 0xff))
+            .map_err(|_| ColorParseError::UnknownName(s.clone()))
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let code = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        3 | 4 => {
+            let digits = if hex.len() == 3 {
+                code << 4 | 0xf
+            } else {
+                code
+            };
+            // Repeats each digit, so that `#fa0` is `#ffaa00`.
+            let code = (0..4).rev().fold(0, |expanded, i| {
+                let digit = digits >> (i * 4) & 0xf;
+                expanded << 8 | digit << 4 | digit
+            });
+            Some(Color::from_code(code))
+        }
+        6 => Some(Color::from_code(code << 8 | 0xff)),
+        8 => Some(Color::from_code(code)),
+        _ => None,
     }
 }
 
-impl Into<image::Rgba<u8>> for Color {
-    fn into(self) -> image::Rgba<u8> {
-        image::Rgba(self.0)
+/// Parses the arguments of `rgb()`, `rgba()`, `hsl()` and `hsla()`, separated by commas or spaces
+/// with an optional `/` before the alpha.
+fn parse_function(name: &str, arguments: &str) -> Option<Color> {
+    let arguments = arguments.trim_end().strip_suffix(')')?;
+    let values = arguments
+        .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+    if values.len() != 3 && values.len() != 4 {
+        return None;
+    }
+    let alpha = match values.get(3) {
+        Some(alpha) => parse_component(alpha, 1.0)?,
+        None => 1.0,
+    };
+    let alpha = (alpha * 255.0).round() as u8;
+
+    match name.trim() {
+        "rgb" | "rgba" => {
+            let mut rgb = [0; 3];
+            for (channel, value) in rgb.iter_mut().zip(&values) {
+                *channel = (parse_component(value, 255.0)? * 255.0).round() as u8;
+            }
+            Some(Color::new(rgb[0], rgb[1], rgb[2], alpha))
+        }
+        "hsl" | "hsla" => {
+            let hue = values[0].strip_suffix("deg").unwrap_or(values[0]);
+            let hue = hue.parse::<f32>().ok().filter(|h| h.is_finite())?;
+            let saturation = parse_component(values[1], 100.0)?;
+            let lightness = parse_component(values[2], 100.0)?;
+            Some(Color::from_hsl(hue, saturation, lightness, alpha))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a number up to `max` or a percentage, returning it from 0.0 to 1.0.
+fn parse_component(value: &str, max: f32) -> Option<f32> {
+    let (number, max) = match value.strip_suffix('%') {
+        Some(number) => (number, 100.0),
+        None => (value, max),
+    };
+    number
+        .parse::<f32>()
+        .ok()
+        .filter(|n| n.is_finite() && *n >= 0.0 && *n <= max)
+        .map(|n| n / max)
+}
+
+impl From<Color> for image::Rgba<u8> {
+    fn from(color: Color) -> image::Rgba<u8> {
+        image::Rgba(color.0)
+    }
+}
+
+impl From<Color> for image::LumaA<u8> {
+    /// Converts with the Rec. 709 luma weights the `image` crate uses.
+    fn from(color: Color) -> image::LumaA<u8> {
+        let [r, g, b, a] = color.0;
+        let luma = (2126 * u32::from(r) + 7152 * u32::from(g) + 722 * u32::from(b)) / 10000;
+        image::LumaA([luma as u8, a])
+    }
+}
+
+impl fmt::Display for Color {
+    /// Formats as `#rrggbb`, or `#rrggbbaa` unless opaque.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, a] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)?;
+        if a != 255 {
+            write!(f, "{:02x}", a)?;
+        }
+        Ok(())
     }
 }
 
@@ -57,6 +373,144 @@ impl Color {
     pub fn green() -> Color {
         Color([0, 255, 0, 255])
     }
+
+    pub fn r(self) -> u8 {
+        self.0[0]
+    }
+
+    pub fn g(self) -> u8 {
+        self.0[1]
+    }
+
+    pub fn b(self) -> u8 {
+        self.0[2]
+    }
+
+    pub fn a(self) -> u8 {
+        self.0[3]
+    }
+
+    pub fn with_alpha(self, alpha: u8) -> Color {
+        Color([self.0[0], self.0[1], self.0[2], alpha])
+    }
+
+    /// Interpolates each channel linearly from `self` at `t = 0.0` to `other` at `t = 1.0`.
+    pub fn lerp(self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let mut channels = [0; 4];
+        for (channel, (from, to)) in channels.iter_mut().zip(self.0.iter().zip(&other.0)) {
+            *channel = (f32::from(*from) + (f32::from(*to) - f32::from(*from)) * t).round() as u8;
+        }
+        Color(channels)
+    }
+
+    /// Composites `self` over `backdrop` with the source-over operator.
+    pub fn over(self, backdrop: Color) -> Color {
+        let alpha = f32::from(self.a()) / 255.0;
+        let backdrop_alpha = f32::from(backdrop.a()) / 255.0 * (1.0 - alpha);
+        let result_alpha = alpha + backdrop_alpha;
+        if result_alpha <= 0.0 {
+            return Color::clear();
+        }
+
+        let mut channels = [0; 4];
+        for (channel, (source, backdrop)) in channels.iter_mut().zip(self.0.iter().zip(&backdrop.0))
+        {
+            let value = f32::from(*source) * alpha + f32::from(*backdrop) * backdrop_alpha;
+            *channel = (value / result_alpha).round() as u8;
+        }
+        channels[3] = (result_alpha * 255.0).round() as u8;
+        Color(channels)
+    }
+
+    /// Multiplies the color channels by the alpha.
+    pub fn premultiply(self) -> Color {
+        let [r, g, b, a] = self.0;
+        let multiply = |c: u8| ((u32::from(c) * u32::from(a) + 127) / 255) as u8;
+        Color([multiply(r), multiply(g), multiply(b), a])
+    }
+
+    /// The relative luminance of WCAG 2 from 0.0 (black) to 1.0 (white), ignoring alpha.
+    pub fn luminance(self) -> f32 {
+        0.2126 * srgb_to_linear(self.r())
+            + 0.7152 * srgb_to_linear(self.g())
+            + 0.0722 * srgb_to_linear(self.b())
+    }
+
+    /// The WCAG 2 contrast ratio from 1.0 to 21.0.
+    pub fn contrast_ratio(self, other: Color) -> f32 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Returns the hue in degrees, and the saturation and lightness from 0.0 to 1.0.
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue();
+        let lightness = (max + min) / 2.0;
+        let saturation = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+        };
+        (hue, saturation, lightness)
+    }
+
+    /// Returns the hue in degrees, and the saturation and value from 0.0 to 1.0.
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue();
+        let saturation = if max == 0.0 { 0.0 } else { (max - min) / max };
+        (hue, saturation, max)
+    }
+
+    /// Creates a color from a hue in degrees, and saturation and lightness from 0.0 to 1.0.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: u8) -> Color {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        Color::from_chroma(hue, chroma, lightness - chroma / 2.0, alpha)
+    }
+
+    /// Creates a color from a hue in degrees, and saturation and value from 0.0 to 1.0.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32, alpha: u8) -> Color {
+        let chroma = value * saturation;
+        Color::from_chroma(hue, chroma, value - chroma, alpha)
+    }
+
+    /// Returns the hue in degrees with the largest and smallest channels from 0.0 to 1.0.
+    fn hue(self) -> (f32, f32, f32) {
+        let (r, g, b) = (
+            f32::from(self.r()) / 255.0,
+            f32::from(self.g()) / 255.0,
+            f32::from(self.b()) / 255.0,
+        );
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        (hue, max, min)
+    }
+
+    /// Creates a color from a hue in degrees, the chroma and the amount `m` added to each channel.
+    fn from_chroma(hue: f32, chroma: f32, m: f32, alpha: u8) -> Color {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let channel = |c: f32| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color::new(channel(r), channel(g), channel(b), alpha)
+    }
 }
 
 #[cfg(test)]
@@ -73,21 +527,21 @@ mod tests {
     #[test]
     fn test_from_str_rgba() {
         let code = "#ffa500ff";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         assert_eq!(&color.0, &[255, 165, 0, 255]);
     }
 
     #[test]
     fn test_from_str_rgb() {
         let code = "#ffa500";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         assert_eq!(&color.0, &[255, 165, 0, 255]);
     }
 
     #[test]
     fn test_clear() {
         let code = "#00000000";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         let clear = Color::clear();
         assert_eq!(color, clear);
     }
@@ -95,7 +549,7 @@ mod tests {
     #[test]
     fn test_white() {
         let code = "#ffffffff";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         let white = Color::white();
         assert_eq!(color, white);
     }
@@ -103,7 +557,7 @@ mod tests {
     #[test]
     fn test_black() {
         let code = "#000000ff";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         let black = Color::black();
         assert_eq!(color, black);
     }
@@ -111,7 +565,7 @@ mod tests {
     #[test]
     fn test_red() {
         let code = "#ff0000ff";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         let red = Color::red();
         assert_eq!(color, red);
     }
@@ -119,7 +573,7 @@ mod tests {
     #[test]
     fn test_green() {
         let code = "#00ff00ff";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         let green = Color::green();
         assert_eq!(color, green);
     }
@@ -127,8 +581,114 @@ mod tests {
     #[test]
     fn test_blue() {
         let code = "#0000ffff";
-        let color = Color::from(code);
+        let color = code.parse::<Color>().unwrap();
         let blue = Color::blue();
         assert_eq!(color, blue);
     }
+
+    #[test]
+    fn test_from_str_short_hex() {
+        assert_eq!("#fa0".parse::<Color>().unwrap().0, [255, 170, 0, 255]);
+        assert_eq!("#fa08".parse::<Color>().unwrap().0, [255, 170, 0, 136]);
+    }
+
+    #[test]
+    fn test_from_str_invalid_hex() {
+        assert!("#ggg".parse::<Color>().is_err());
+        assert!("#ffa50".parse::<Color>().is_err());
+        assert!("#".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_from_str_name() {
+        assert_eq!("red".parse::<Color>().unwrap(), Color::red());
+        assert_eq!(
+            "RebeccaPurple".parse::<Color>().unwrap().0,
+            [102, 51, 153, 255]
+        );
+        assert_eq!("transparent".parse::<Color>().unwrap(), Color::clear());
+        assert!("reddish".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_from_str_rgb_function() {
+        let orange = [255, 165, 0, 255];
+        assert_eq!("rgb(255, 165, 0)".parse::<Color>().unwrap().0, orange);
+        assert_eq!("rgb(100% 64.7% 0%)".parse::<Color>().unwrap().0, orange);
+        assert_eq!("rgba(255,165,0,0.5)".parse::<Color>().unwrap().0[3], 128);
+        assert_eq!("rgb(255 165 0 / 50%)".parse::<Color>().unwrap().0[3], 128);
+        assert!("rgb(256, 0, 0)".parse::<Color>().is_err());
+        assert!("rgb(0, 0)".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_from_str_hsl_function() {
+        assert_eq!("hsl(0, 100%, 50%)".parse::<Color>().unwrap(), Color::red());
+        assert_eq!(
+            "hsl(120deg 100% 25%)".parse::<Color>().unwrap().0,
+            [0, 128, 0, 255]
+        );
+        assert_eq!(
+            "hsla(240, 100%, 50%, 0)".parse::<Color>().unwrap().0,
+            [0, 0, 255, 0]
+        );
+        assert!("hsl(0, 100%)".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_lerp() {
+        let gray = Color::black().lerp(Color::white(), 0.5);
+        assert_eq!(gray.0, [128, 128, 128, 255]);
+        assert_eq!(Color::red().lerp(Color::blue(), 1.5), Color::blue());
+    }
+
+    #[test]
+    fn test_over() {
+        let translucent_red = Color::red().with_alpha(128);
+        assert_eq!(translucent_red.over(Color::white()).0, [255, 127, 127, 255]);
+        assert_eq!(translucent_red.over(Color::clear()), translucent_red);
+        assert_eq!(Color::clear().over(Color::clear()), Color::clear());
+    }
+
+    #[test]
+    fn test_premultiply() {
+        let color = Color::new(255, 128, 0, 128).premultiply();
+        assert_eq!(color.0, [128, 64, 0, 128]);
+    }
+
+    #[test]
+    fn test_contrast_ratio() {
+        assert!((Color::black().contrast_ratio(Color::white()) - 21.0).abs() < 0.01);
+        assert!((Color::red().contrast_ratio(Color::red()) - 1.0).abs() < 0.01);
+        assert!(Color::white().luminance() > Color::green().luminance());
+    }
+
+    #[test]
+    fn test_hsl_hsv() {
+        let orange = Color::new(255, 165, 0, 255);
+        let (h, s, l) = orange.to_hsl();
+        assert!((h - 38.8).abs() < 0.1 && (s - 1.0).abs() < 0.001 && (l - 0.5).abs() < 0.001);
+        assert_eq!(Color::from_hsl(h, s, l, 255), orange);
+
+        let (h, s, v) = orange.to_hsv();
+        assert_eq!((s, v), (1.0, 1.0));
+        assert_eq!(Color::from_hsv(h, s, v, 255), orange);
+        assert_eq!(Color::white().to_hsv(), (0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_luma_alpha() {
+        let luma: image::LumaA<u8> = Color::white().with_alpha(10).into();
+        assert_eq!(luma, image::LumaA([255, 10]));
+        let luma: image::LumaA<u8> = Color::green().into();
+        assert_eq!(luma[0], 182);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Color::new(255, 165, 0, 255).to_string(), "#ffa500");
+        assert_eq!(Color::clear().to_string(), "#00000000");
+        let color = "#fa08".parse::<Color>().unwrap();
+        assert_eq!(color.to_string().parse::<Color>().unwrap(), color);
+    }
 }
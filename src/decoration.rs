@@ -1,13 +1,45 @@
-#[derive(Debug, Clone, Copy)]
+use crate::color;
+use failure::Fail;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Fail)]
+pub enum DecorationError {
+    #[fail(display = "unknown decoration level: {}", _0)]
+    UnknownLevel(String),
+    #[fail(display = "invalid style specification: {}", _0)]
+    InvalidStyle(String),
+    #[fail(display = "invalid style color: {}", _0)]
+    InvalidColor(#[cause] color::ColorParseError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Decoration {
+    Heading1,
+    Heading2,
+    Heading3,
     Larger,
     Normal,
     Smaller,
 }
 
 impl Decoration {
+    pub fn from_name(name: &str) -> Result<Decoration, DecorationError> {
+        match name.to_lowercase().as_str() {
+            "h1" | "#" => Ok(Decoration::Heading1),
+            "h2" | "##" => Ok(Decoration::Heading2),
+            "h3" | "###" => Ok(Decoration::Heading3),
+            "larger" | "*" => Ok(Decoration::Larger),
+            "normal" => Ok(Decoration::Normal),
+            "smaller" | "_" => Ok(Decoration::Smaller),
+            _ => Err(DecorationError::UnknownLevel(name.to_owned())),
+        }
+    }
+
     pub fn scale_factor(self) -> f32 {
         match self {
+            Decoration::Heading1 => 2.0,
+            Decoration::Heading2 => 1.6,
+            Decoration::Heading3 => 1.3,
             Decoration::Larger => 1.3,
             Decoration::Normal => 1.0,
             Decoration::Smaller => 0.6,
@@ -15,6 +47,83 @@ impl Decoration {
     }
 }
 
+/// Drawing attributes of a decoration level.
+///
+/// `font_path` and `color` fall back to the `--font` and `--color` options when `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    pub scale_factor: f32,
+    pub font_path: Option<String>,
+    pub color: Option<color::Color>,
+}
+
+impl Style {
+    pub fn new(decoration: Decoration) -> Style {
+        Style {
+            scale_factor: decoration.scale_factor(),
+            font_path: None,
+            color: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Styles(HashMap<Decoration, Style>);
+
+impl Styles {
+    pub fn get(&self, decoration: Decoration) -> Style {
+        self.0
+            .get(&decoration)
+            .cloned()
+            .unwrap_or_else(|| Style::new(decoration))
+    }
+
+    /// Applies a specification such as `h1:scale=2.4,font=Bold.ttf,color=#ff0000ff`.
+    pub fn apply(&mut self, spec: &str) -> Result<(), DecorationError> {
+        let invalid = || DecorationError::InvalidStyle(spec.to_owned());
+
+        let mut split = spec.splitn(2, ':');
+        let decoration = Decoration::from_name(split.next().ok_or_else(invalid)?.trim())?;
+        let attributes = split.next().ok_or_else(invalid)?;
+
+        let style = self
+            .0
+            .entry(decoration)
+            .or_insert_with(|| Style::new(decoration));
+
+        for attribute in color::split_list(attributes).filter(|s| !s.is_empty()) {
+            let mut split = attribute.splitn(2, '=');
+            let (key, value) = match (split.next(), split.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => return Err(invalid()),
+            };
+
+            match key {
+                "scale" => {
+                    style.scale_factor = value
+                        .parse()
+                        .ok()
+                        .filter(|scale: &f32| scale.is_finite() && *scale > 0.0)
+                        .ok_or_else(invalid)?
+                }
+                "font" => style.font_path = Some(value.to_owned()),
+                "color" => {
+                    style.color = Some(value.parse().map_err(DecorationError::InvalidColor)?)
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn font_paths(&self) -> impl Iterator<Item = (Decoration, &str)> {
+        self.0
+            .iter()
+            .filter_map(|(d, s)| s.font_path.as_ref().map(|p| (*d, p.as_str())))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DecoratedString {
     pub decoration: Decoration,
@@ -23,22 +132,28 @@ pub struct DecoratedString {
 
 impl From<&str> for DecoratedString {
     fn from(s: &str) -> DecoratedString {
-        let (decoration, s) = if s.chars().nth(0).map(|c| c == '*').unwrap_or(false)
-            && s.chars().last().map(|c| c == '*').unwrap_or(false)
-        {
+        let headings = [
+            ("### ", Decoration::Heading3),
+            ("## ", Decoration::Heading2),
+            ("# ", Decoration::Heading1),
+        ];
+
+        if let Some((marker, decoration)) = headings.iter().find(|(m, _)| s.starts_with(m)) {
+            return DecoratedString::new(*decoration, &unescape(s[marker.len()..].trim_start()));
+        }
+
+        let (decoration, s) = if is_enclosed_by(s, '*') {
             let mut s = s.chars().skip(1).collect::<String>();
             s.pop();
             (Decoration::Larger, s)
-        } else if s.chars().nth(0).map(|c| c == '_').unwrap_or(false)
-            && s.chars().last().map(|c| c == '_').unwrap_or(false)
-        {
+        } else if is_enclosed_by(s, '_') {
             let mut s = s.chars().skip(1).collect::<String>();
             s.pop();
             (Decoration::Smaller, s)
         } else {
             (Decoration::Normal, s.to_owned())
         };
-        DecoratedString::new(decoration, &s)
+        DecoratedString::new(decoration, &unescape(&s))
     }
 }
 
@@ -49,4 +164,206 @@ impl DecoratedString {
             body: body.to_owned(),
         }
     }
+
+    /// Creates a `Normal` string without interpreting any markup.
+    pub fn plain(body: &str) -> DecoratedString {
+        DecoratedString::new(Decoration::Normal, body)
+    }
+}
+
+/// Splits a text into decorated lines.
+///
+/// Both real line breaks and `\n` sequences start a new line.
+/// If `markup` is `false`, every line is drawn as-is with `Normal` decoration.
+pub fn parse(text: &str, markup: bool) -> Vec<DecoratedString> {
+    split_lines(text)
+        .iter()
+        .map(|l| {
+            if markup {
+                DecoratedString::from(l.as_str())
+            } else {
+                DecoratedString::plain(l)
+            }
+        })
+        .collect()
+}
+
+fn split_lines(text: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let line = lines.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => lines.push(String::new()),
+                Some(next) => {
+                    line.push(c);
+                    line.push(next);
+                }
+                None => line.push(c),
+            },
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => lines.push(String::new()),
+            _ => line.push(c),
+        }
+    }
+
+    if lines.len() > 1 && lines.last().map(String::is_empty).unwrap_or(false) {
+        lines.pop();
+    }
+    lines
+}
+
+fn is_enclosed_by(s: &str, marker: char) -> bool {
+    let mut chars = s.chars();
+    if chars.next() != Some(marker) || chars.next_back() != Some(marker) {
+        return false;
+    }
+    let escapes = chars.rev().take_while(|c| *c == '\\').count();
+    escapes % 2 == 0
+}
+
+/// Removes backslashes escaping `\\`, `*`, `_` and `#`.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if "\\*_#".contains(next) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str_headings() {
+        let h1 = DecoratedString::from("# Title");
+        assert_eq!(h1.decoration, Decoration::Heading1);
+        assert_eq!(h1.body, "Title");
+
+        let h2 = DecoratedString::from("## Subtitle");
+        assert_eq!(h2.decoration, Decoration::Heading2);
+        assert_eq!(h2.body, "Subtitle");
+
+        let h3 = DecoratedString::from("### Section");
+        assert_eq!(h3.decoration, Decoration::Heading3);
+        assert_eq!(h3.body, "Section");
+    }
+
+    #[test]
+    fn test_from_str_hash_without_space() {
+        let s = DecoratedString::from("#hashtag");
+        assert_eq!(s.decoration, Decoration::Normal);
+        assert_eq!(s.body, "#hashtag");
+    }
+
+    #[test]
+    fn test_from_str_emphasis() {
+        let larger = DecoratedString::from("*Larger*");
+        assert_eq!(larger.decoration, Decoration::Larger);
+        assert_eq!(larger.body, "Larger");
+
+        let smaller = DecoratedString::from("_Smaller_");
+        assert_eq!(smaller.decoration, Decoration::Smaller);
+        assert_eq!(smaller.body, "Smaller");
+    }
+
+    #[test]
+    fn test_from_str_escaped() {
+        let s = DecoratedString::from("\\*NEW*");
+        assert_eq!(s.decoration, Decoration::Normal);
+        assert_eq!(s.body, "*NEW*");
+
+        let s = DecoratedString::from("_config\\_");
+        assert_eq!(s.decoration, Decoration::Normal);
+        assert_eq!(s.body, "_config_");
+
+        let s = DecoratedString::from("\\# not a heading");
+        assert_eq!(s.decoration, Decoration::Normal);
+        assert_eq!(s.body, "# not a heading");
+
+        let s = DecoratedString::from("*C:\\\\*");
+        assert_eq!(s.decoration, Decoration::Larger);
+        assert_eq!(s.body, "C:\\");
+    }
+
+    #[test]
+    fn test_parse_newline_escape() {
+        let lines = parse("# Title\\nbody\nlast", true);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].decoration, Decoration::Heading1);
+        assert_eq!(lines[0].body, "Title");
+        assert_eq!(lines[1].body, "body");
+        assert_eq!(lines[2].body, "last");
+
+        let lines = parse("C:\\\\new\r\n", true);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].body, "C:\\new");
+    }
+
+    #[test]
+    fn test_parse_no_markup() {
+        let lines = parse("*NEW*\\n_config_", false);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].decoration, Decoration::Normal);
+        assert_eq!(lines[0].body, "*NEW*");
+        assert_eq!(lines[1].decoration, Decoration::Normal);
+        assert_eq!(lines[1].body, "_config_");
+    }
+
+    #[test]
+    fn test_styles_default() {
+        let styles = Styles::default();
+        assert_eq!(styles.get(Decoration::Heading1).scale_factor, 2.0);
+        assert_eq!(styles.get(Decoration::Normal).scale_factor, 1.0);
+        assert_eq!(styles.get(Decoration::Normal).font_path, None);
+    }
+
+    #[test]
+    fn test_styles_apply() {
+        let mut styles = Styles::default();
+        styles
+            .apply("h1:scale=2.4,font=Bold.ttf,color=#ff0000ff")
+            .unwrap();
+        let style = styles.get(Decoration::Heading1);
+        assert_eq!(style.scale_factor, 2.4);
+        assert_eq!(style.font_path, Some("Bold.ttf".to_owned()));
+        assert_eq!(style.color, Some(color::Color::red()));
+        assert_eq!(styles.get(Decoration::Heading2).scale_factor, 1.6);
+
+        styles.apply("h2:color=rgb(0, 0, 255),scale=2").unwrap();
+        assert_eq!(
+            styles.get(Decoration::Heading2).color,
+            Some(color::Color::blue())
+        );
+    }
+
+    #[test]
+    fn test_styles_apply_invalid() {
+        let mut styles = Styles::default();
+        assert!(styles.apply("h4:scale=2.0").is_err());
+        assert!(styles.apply("h1:scale=large").is_err());
+        assert!(styles.apply("h1:weight=bold").is_err());
+        assert!(styles.apply("h1").is_err());
+        assert!(styles.apply("h1:color=#ggg").is_err());
+    }
+
+    #[test]
+    fn test_styles_apply_invalid_scale() {
+        let mut styles = Styles::default();
+        for scale in &["-1", "0", "NaN", "inf"] {
+            assert!(styles.apply(&format!("h1:scale={}", scale)).is_err());
+        }
+        assert_eq!(styles.get(Decoration::Heading1).scale_factor, 2.0);
+    }
 }
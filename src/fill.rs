@@ -0,0 +1,419 @@
+use crate::color::{self, Color, ColorParseError};
+use crate::overlay;
+use failure::Fail;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Fail)]
+pub enum FillError {
+    #[fail(
+        display = "invalid gradient: {} (expected linear([ANGLEdeg,] COLOR [POSITION%], ...) or radial(COLOR [POSITION%], ...))",
+        _0
+    )]
+    InvalidGradient(String),
+    #[fail(
+        display = "invalid pattern: {} (expected checker(SIZE, COLOR, COLOR) or stripes([ANGLEdeg,] WIDTH, COLOR, COLOR))",
+        _0
+    )]
+    InvalidPattern(String),
+    #[fail(display = "{}", _0)]
+    Color(#[cause] ColorParseError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    /// A gradient along a line at `angle` degrees clockwise from the top, as in CSS.
+    Linear { angle: f32 },
+    /// An elliptical gradient from the center to the corners.
+    Radial,
+}
+
+impl Shape {
+    /// Returns the offset on the gradient line of the pixel at `(x, y)` in `rect`.
+    fn offset(self, (x, y): (i32, i32), rect: (i32, i32, i32, i32)) -> f32 {
+        let (width, height) = (rect.2.max(1) as f32, rect.3.max(1) as f32);
+        let dx = x as f32 + 0.5 - (rect.0 as f32 + width / 2.0);
+        let dy = y as f32 + 0.5 - (rect.1 as f32 + height / 2.0);
+
+        match self {
+            Shape::Linear { angle } => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let length = (width * sin).abs() + (height * cos).abs();
+                (dx * sin - dy * cos) / length + 0.5
+            }
+            Shape::Radial => (dx / width * 2.0).hypot(dy / height * 2.0) / std::f32::consts::SQRT_2,
+        }
+    }
+}
+
+/// Colors interpolated in linear light between stops at offsets from 0.0 to 1.0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub shape: Shape,
+    pub stops: Vec<(f32, Color)>,
+}
+
+impl Gradient {
+    /// Parses `linear([ANGLEdeg,] STOP, STOP, ...)` or `radial(STOP, STOP, ...)`
+    /// where `STOP` is a color optionally followed by its position such as `#ff6a00 30%`.
+    ///
+    /// Linear gradients run from top to bottom (180deg) by default.
+    fn parse(s: &str) -> Result<Gradient, FillError> {
+        let invalid = || FillError::InvalidGradient(s.to_owned());
+
+        let open = s.find('(').ok_or_else(invalid)?;
+        let arguments = s[open + 1..].strip_suffix(')').ok_or_else(invalid)?;
+        let mut arguments = color::split_list(arguments)
+            .map(str::trim)
+            .collect::<Vec<_>>();
+
+        let shape = match s[..open].trim() {
+            "linear" => {
+                let angle = arguments.first().and_then(|a| parse_angle(a));
+                if angle.is_some() {
+                    arguments.remove(0);
+                }
+                Shape::Linear {
+                    angle: angle.unwrap_or(180.0),
+                }
+            }
+            "radial" => Shape::Radial,
+            _ => return Err(invalid()),
+        };
+        if arguments.len() < 2 {
+            return Err(invalid());
+        }
+
+        let mut stops = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            let mut split = argument.rsplitn(2, char::is_whitespace);
+            let (position, color) = match (split.next(), split.next()) {
+                (Some(position), Some(color)) if position.ends_with('%') => {
+                    let position = position[..position.len() - 1]
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|p| p.is_finite())
+                        .ok_or_else(invalid)?;
+                    (Some(position / 100.0), color)
+                }
+                _ => (None, argument),
+            };
+            let color = color.parse().map_err(FillError::Color)?;
+            stops.push((position, color));
+        }
+
+        Ok(Gradient {
+            shape,
+            stops: distribute(stops),
+        })
+    }
+
+    /// Returns the color at `offset` along the gradient.
+    pub fn color_at(&self, offset: f32) -> Color {
+        let (first, last) = (self.stops[0], self.stops[self.stops.len() - 1]);
+        if offset.is_nan() || offset <= first.0 {
+            return first.1;
+        }
+        if offset >= last.0 {
+            return last.1;
+        }
+
+        let end = self
+            .stops
+            .iter()
+            .position(|(position, _)| *position > offset)
+            .unwrap_or(self.stops.len() - 1);
+        let ((from, from_color), (to, to_color)) = (self.stops[end - 1], self.stops[end]);
+        interpolate(from_color, to_color, (offset - from) / (to - from))
+    }
+}
+
+fn parse_angle(s: &str) -> Option<f32> {
+    s.strip_suffix("deg")
+        .unwrap_or(s)
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|a| a.is_finite())
+}
+
+/// Fills in missing stop positions as CSS does: the first and last stops default to the ends,
+/// positions never decrease and stops without one are spaced evenly between their neighbors.
+fn distribute(stops: Vec<(Option<f32>, Color)>) -> Vec<(f32, Color)> {
+    let last = stops.len() - 1;
+    let mut positions = stops.iter().map(|(p, _)| *p).collect::<Vec<_>>();
+    positions[0] = positions[0].or(Some(0.0));
+    positions[last] = positions[last].or(Some(1.0));
+
+    let mut max = 0.0f32;
+    for position in positions.iter_mut().flatten() {
+        max = max.max(*position);
+        *position = max;
+    }
+
+    let mut start = 0;
+    for index in 1..=last {
+        if let Some(end) = positions[index] {
+            let from = positions[start].unwrap_or(0.0);
+            let steps = (index - start) as f32;
+            for (step, position) in positions[start + 1..index].iter_mut().enumerate() {
+                *position = Some(from + (end - from) * (step + 1) as f32 / steps);
+            }
+            start = index;
+        }
+    }
+
+    positions
+        .into_iter()
+        .zip(stops)
+        .map(|(position, (_, color))| (position.unwrap_or(0.0), color))
+        .collect()
+}
+
+/// Interpolates premultiplied colors in linear light.
+fn interpolate(from: Color, to: Color, t: f32) -> Color {
+    let alpha = |c: Color| f32::from(c.a()) / 255.0;
+    let (from_alpha, to_alpha) = (alpha(from), alpha(to));
+    let result_alpha = from_alpha + (to_alpha - from_alpha) * t;
+    if result_alpha <= 0.0 {
+        return Color::clear();
+    }
+
+    let channel = |a: u8, b: u8| {
+        let a = color::srgb_to_linear(a) * from_alpha;
+        let b = color::srgb_to_linear(b) * to_alpha;
+        color::linear_to_srgb((a + (b - a) * t) / result_alpha)
+    };
+    Color::new(
+        channel(from.r(), to.r()),
+        channel(from.g(), to.g()),
+        channel(from.b(), to.b()),
+        (result_alpha * 255.0).round() as u8,
+    )
+}
+
+/// Two colors repeated in a regular pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// Squares of `size` pixels.
+    Checker { size: u32, colors: [Color; 2] },
+    /// Stripes of `width` pixels across the line at `angle` degrees clockwise from the top.
+    Stripes {
+        angle: f32,
+        width: u32,
+        colors: [Color; 2],
+    },
+}
+
+impl Pattern {
+    /// Parses `checker(SIZE, COLOR, COLOR)` or `stripes([ANGLEdeg,] WIDTH, COLOR, COLOR)`.
+    ///
+    /// Stripes run diagonally (45deg) by default.
+    fn parse(s: &str) -> Result<Pattern, FillError> {
+        let invalid = || FillError::InvalidPattern(s.to_owned());
+
+        let open = s.find('(').ok_or_else(invalid)?;
+        let arguments = s[open + 1..].strip_suffix(')').ok_or_else(invalid)?;
+        let mut arguments = color::split_list(arguments)
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        let name = s[..open].trim();
+
+        let angle = match arguments.first() {
+            Some(angle) if name == "stripes" && angle.ends_with("deg") => {
+                let angle = parse_angle(angle).ok_or_else(invalid)?;
+                arguments.remove(0);
+                angle
+            }
+            _ => 45.0,
+        };
+        if arguments.len() != 3 {
+            return Err(invalid());
+        }
+        let size = arguments[0]
+            .parse::<u32>()
+            .ok()
+            .filter(|s| *s > 0)
+            .ok_or_else(invalid)?;
+        let colors = [
+            arguments[1].parse().map_err(FillError::Color)?,
+            arguments[2].parse().map_err(FillError::Color)?,
+        ];
+
+        match name {
+            "checker" => Ok(Pattern::Checker { size, colors }),
+            "stripes" => Ok(Pattern::Stripes {
+                angle,
+                width: size,
+                colors,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Returns the color at `(x, y)` of a pattern starting at the top-left corner of `rect`.
+    fn at(&self, (x, y): (i32, i32), rect: (i32, i32, i32, i32)) -> Color {
+        let (dx, dy) = (x - rect.0, y - rect.1);
+        match self {
+            Pattern::Checker { size, colors } => {
+                let size = *size as i32;
+                colors[(dx.div_euclid(size) + dy.div_euclid(size)).rem_euclid(2) as usize]
+            }
+            Pattern::Stripes {
+                angle,
+                width,
+                colors,
+            } => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let offset = (dx as f32 + 0.5) * sin - (dy as f32 + 0.5) * cos;
+                colors[((offset / *width as f32).floor() as i32).rem_euclid(2) as usize]
+            }
+        }
+    }
+}
+
+/// A solid color, a gradient or a pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fill {
+    Solid(Color),
+    Gradient(Gradient),
+    Pattern(Pattern),
+}
+
+impl FromStr for Fill {
+    type Err = FillError;
+
+    /// Parses a gradient starting with `linear(` or `radial(`,
+    /// a pattern starting with `checker(` or `stripes(`, or a color.
+    fn from_str(s: &str) -> Result<Fill, FillError> {
+        let s = s.trim().to_lowercase();
+        if s.starts_with("linear(") || s.starts_with("radial(") {
+            Gradient::parse(&s).map(Fill::Gradient)
+        } else if s.starts_with("checker(") || s.starts_with("stripes(") {
+            Pattern::parse(&s).map(Fill::Pattern)
+        } else {
+            s.parse().map(Fill::Solid).map_err(FillError::Color)
+        }
+    }
+}
+
+impl From<Color> for Fill {
+    fn from(color: Color) -> Fill {
+        Fill::Solid(color)
+    }
+}
+
+impl Fill {
+    /// Returns the color at `(x, y)` of a fill spanning `rect` as `(x, y, width, height)`.
+    pub fn at(&self, point: (i32, i32), rect: (i32, i32, i32, i32)) -> Color {
+        match self {
+            Fill::Solid(color) => *color,
+            Fill::Gradient(gradient) => gradient.color_at(gradient.shape.offset(point, rect)),
+            Fill::Pattern(pattern) => pattern.at(point, rect),
+        }
+    }
+
+    /// Blends the fill over `rect` of `canvas`, clipped to the canvas.
+    pub fn paint<P>(&self, canvas: &mut image::ImageBuffer<P, Vec<u8>>, rect: (i32, i32, i32, i32))
+    where
+        P: image::Pixel<Subpixel = u8> + From<Color> + 'static,
+    {
+        let (width, height) = (canvas.width() as i32, canvas.height() as i32);
+        for y in rect.1.max(0)..(rect.1 + rect.3).min(height) {
+            for x in rect.0.max(0)..(rect.0 + rect.2).min(width) {
+                overlay::blend(
+                    canvas.get_pixel_mut(x as u32, y as u32),
+                    &P::from(self.at((x, y), rect)),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Gradient {
+        match s.parse::<Fill>().unwrap() {
+            Fill::Gradient(gradient) => gradient,
+            fill => panic!("not a gradient: {:?}", fill),
+        }
+    }
+
+    #[test]
+    fn test_parse_solid() {
+        assert_eq!("red".parse::<Fill>().unwrap(), Fill::Solid(Color::red()));
+        assert!("linear(90deg, red)".parse::<Fill>().is_err());
+        assert!("conic(red, blue)".parse::<Fill>().is_err());
+        assert!("linear(red, bluish)".parse::<Fill>().is_err());
+    }
+
+    #[test]
+    fn test_parse_linear() {
+        let gradient = parse("linear(90deg, #ff6a00, rgb(238, 9, 121) 80%)");
+        assert_eq!(gradient.shape, Shape::Linear { angle: 90.0 });
+        assert_eq!(
+            gradient.stops,
+            vec![
+                (0.0, Color::new(255, 106, 0, 255)),
+                (0.8, Color::new(238, 9, 121, 255))
+            ]
+        );
+        assert_eq!(
+            parse("linear(red, blue)").shape,
+            Shape::Linear { angle: 180.0 }
+        );
+    }
+
+    #[test]
+    fn test_distribute() {
+        let gradient = parse("radial(red, green, blue 60%, white 40%, black)");
+        let positions = gradient.stops.iter().map(|(p, _)| *p).collect::<Vec<_>>();
+        assert_eq!(positions, vec![0.0, 0.3, 0.6, 0.6, 1.0]);
+    }
+
+    #[test]
+    fn test_color_at_linear_light() {
+        let gradient = parse("linear(black, white)");
+        assert_eq!(gradient.color_at(-1.0), Color::black());
+        assert_eq!(gradient.color_at(1.0), Color::white());
+        // The middle is half the light of white, brighter than the sRGB midpoint.
+        assert_eq!(gradient.color_at(0.5).r(), 188);
+    }
+
+    #[test]
+    fn test_at() {
+        let fill = "linear(90deg, red, blue)".parse::<Fill>().unwrap();
+        let rect = (10, 0, 100, 10);
+        assert_eq!(fill.at((10, 5), rect).r(), 254);
+        assert_eq!(fill.at((109, 5), rect).b(), 254);
+
+        let fill = "radial(white, black)".parse::<Fill>().unwrap();
+        assert_eq!(fill.at((0, 0), (-50, -50, 101, 101)), Color::white());
+    }
+
+    #[test]
+    fn test_paint() {
+        let mut canvas = image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 0, 255]));
+        Fill::Solid(Color::white()).paint(&mut canvas, (2, -1, 5, 2));
+        assert_eq!(canvas.get_pixel(2, 0), &image::Rgba([255, 255, 255, 255]));
+        assert_eq!(canvas.get_pixel(2, 1), &image::Rgba([0, 0, 0, 255]));
+        assert_eq!(canvas.get_pixel(1, 0), &image::Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let fill = "checker(2, black, white)".parse::<Fill>().unwrap();
+        let rect = (1, 1, 10, 10);
+        assert_eq!(fill.at((1, 2), rect), Color::black());
+        assert_eq!(fill.at((3, 2), rect), Color::white());
+        assert_eq!(fill.at((0, 0), rect), Color::black());
+
+        let fill = "stripes(90deg, 3, red, blue)".parse::<Fill>().unwrap();
+        assert_eq!(fill.at((3, 0), (0, 0, 10, 10)), Color::blue());
+        assert_eq!(fill.at((3, 9), (0, 0, 10, 10)), Color::blue());
+        assert_eq!(fill.at((6, 0), (0, 0, 10, 10)), Color::red());
+        assert!("checker(0, red, blue)".parse::<Fill>().is_err());
+        assert!("stripes(3, red)".parse::<Fill>().is_err());
+    }
+}
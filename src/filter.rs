@@ -0,0 +1,336 @@
+use failure::Fail;
+
+#[derive(Debug, Clone, Fail)]
+pub enum FilterError {
+    #[fail(display = "invalid region: {}", _0)]
+    InvalidRegion(String),
+    #[fail(display = "invalid unsharp mask: {}", _0)]
+    InvalidUnsharpMask(String),
+}
+
+/// A convolution applied to the input image.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    GaussianBlur {
+        sigma: f32,
+    },
+    BoxBlur {
+        radius: u32,
+    },
+    /// Adds `amount` times the difference from a Gaussian blur of `sigma` where it is at least `threshold`.
+    UnsharpMask {
+        sigma: f32,
+        amount: f32,
+        threshold: u8,
+    },
+}
+
+impl Filter {
+    /// A light sharpen, an unsharp mask with a radius of one pixel.
+    pub fn sharpen(amount: f32) -> Filter {
+        Filter::UnsharpMask {
+            sigma: 1.0,
+            amount,
+            threshold: 0,
+        }
+    }
+
+    /// Parses `RADIUS,AMOUNT[,THRESHOLD]` such as `1.5,0.8,3`.
+    pub fn parse_unsharp_mask(s: &str) -> Result<Filter, FilterError> {
+        let error = || FilterError::InvalidUnsharpMask(s.to_owned());
+        let values = s.split(',').map(str::trim).collect::<Vec<_>>();
+        if values.len() < 2 || values.len() > 3 {
+            return Err(error());
+        }
+
+        let sigma = values[0].parse::<f32>().map_err(|_| error())?;
+        let amount = values[1].parse::<f32>().map_err(|_| error())?;
+        let threshold = match values.get(2) {
+            Some(threshold) => threshold.parse::<u8>().map_err(|_| error())?,
+            None => 0,
+        };
+        if !(sigma.is_finite() && sigma > 0.0 && amount.is_finite() && amount >= 0.0) {
+            return Err(error());
+        }
+        Ok(Filter::UnsharpMask {
+            sigma,
+            amount,
+            threshold,
+        })
+    }
+
+    fn apply<P>(&self, image: &image::ImageBuffer<P, Vec<u8>>) -> image::ImageBuffer<P, Vec<u8>>
+    where
+        P: image::Pixel<Subpixel = u8> + 'static,
+    {
+        match *self {
+            Filter::GaussianBlur { sigma } => {
+                blur(image, |i| imageproc::filter::gaussian_blur_f32(i, sigma))
+            }
+            Filter::BoxBlur { radius } => {
+                // Edge pixels are repeated, so a kernel wider than the image only weights them more.
+                let radius = radius.min(image.width().max(image.height())) as usize;
+                let kernel = vec![1.0 / (radius * 2 + 1) as f32; radius * 2 + 1];
+                blur(image, |i| {
+                    imageproc::filter::separable_filter_equal(i, &kernel)
+                })
+            }
+            Filter::UnsharpMask {
+                sigma,
+                amount,
+                threshold,
+            } => {
+                let blurred = blur(image, |i| imageproc::filter::gaussian_blur_f32(i, sigma));
+                let mut sharpened = image.clone();
+                let color_channels = color_channels::<P>();
+
+                for (pixel, blurred) in sharpened.pixels_mut().zip(blurred.pixels()) {
+                    let channels = pixel.channels_mut()[..color_channels].iter_mut();
+                    for (channel, blurred) in channels.zip(blurred.channels()) {
+                        let difference = f32::from(*channel) - f32::from(*blurred);
+                        if difference.abs() >= f32::from(threshold) {
+                            *channel = (f32::from(*channel) + difference * amount)
+                                .round()
+                                .clamp(0.0, 255.0) as u8;
+                        }
+                    }
+                }
+                sharpened
+            }
+        }
+    }
+}
+
+/// The number of color channels, which are followed by alpha in 2 and 4 channel pixels.
+pub fn color_channels<P>() -> usize
+where
+    P: image::Pixel,
+{
+    match P::CHANNEL_COUNT {
+        2 => 1,
+        4 => 3,
+        count => count as usize,
+    }
+}
+
+/// Blurs with `filter` on premultiplied alpha so that transparent pixels do not bleed their color.
+fn blur<P, F>(image: &image::ImageBuffer<P, Vec<u8>>, filter: F) -> image::ImageBuffer<P, Vec<u8>>
+where
+    P: image::Pixel<Subpixel = u8> + 'static,
+    F: Fn(&image::ImageBuffer<P, Vec<u8>>) -> image::ImageBuffer<P, Vec<u8>>,
+{
+    let color_channels = color_channels::<P>();
+    if color_channels == P::CHANNEL_COUNT as usize {
+        return filter(image);
+    }
+
+    let mut premultiplied = image.clone();
+    premultiplied.pixels_mut().for_each(premultiply);
+    let mut blurred = filter(&premultiplied);
+    blurred.pixels_mut().for_each(unpremultiply);
+    blurred
+}
+
+/// Multiplies the color channels of a pixel by its alpha if it has one, so that interpolating
+/// transparent pixels does not bleed their color.
+pub fn premultiply<P>(pixel: &mut P)
+where
+    P: image::Pixel<Subpixel = u8>,
+{
+    let color_channels = color_channels::<P>();
+    let (colors, alpha) = pixel.channels_mut().split_at_mut(color_channels);
+    if let Some(alpha) = alpha.first() {
+        for channel in colors {
+            *channel = (u32::from(*channel) * u32::from(*alpha) / 255) as u8;
+        }
+    }
+}
+
+/// Divides the color channels of a premultiplied pixel by its alpha.
+pub fn unpremultiply<P>(pixel: &mut P)
+where
+    P: image::Pixel<Subpixel = u8>,
+{
+    let color_channels = color_channels::<P>();
+    let (colors, alpha) = pixel.channels_mut().split_at_mut(color_channels);
+    if let Some(&alpha) = alpha.first().filter(|a| **a > 0) {
+        for channel in colors {
+            *channel = (u32::from(*channel) * 255 / u32::from(alpha)).min(255) as u8;
+        }
+    }
+}
+
+/// A rectangle of the image in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// Parses a geometry `WIDTHxHEIGHT+X+Y` such as `200x100+10+20`.
+    pub fn parse(s: &str) -> Result<Region, FilterError> {
+        let error = || FilterError::InvalidRegion(s.to_owned());
+        let values = s
+            .split(&['x', '+'][..])
+            .map(|v| v.parse::<u32>().map_err(|_| error()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match values.as_slice() {
+            [width, height, x, y] => Ok(Region {
+                x: *x,
+                y: *y,
+                width: *width,
+                height: *height,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Filters applied in order to the whole image or to a region of it.
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    pub filters: Vec<Filter>,
+    pub region: Option<Region>,
+}
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn apply<P>(&self, image: &mut image::ImageBuffer<P, Vec<u8>>)
+    where
+        P: image::Pixel<Subpixel = u8> + 'static,
+    {
+        if self.filters.is_empty() {
+            return;
+        }
+
+        let (width, height) = image.dimensions();
+        let region = self.region.unwrap_or(Region {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+        let x = region.x.min(width);
+        let y = region.y.min(height);
+        let (width, height) = (region.width.min(width - x), region.height.min(height - y));
+        if width == 0 || height == 0 {
+            log::warn!("filter region {:?} is outside of the image", region);
+            return;
+        }
+
+        let mut filtered = image::imageops::crop(image, x, y, width, height).to_image();
+        for filter in &self.filters {
+            filtered = filter.apply(&filtered);
+        }
+        for (dx, dy, pixel) in filtered.enumerate_pixels() {
+            image.put_pixel(x + dx, y + dy, *pixel);
+        }
+    }
+
+    /// Filters a decoded image of any color type.
+    pub fn apply_dynamic(&self, image: image::DynamicImage) -> image::DynamicImage {
+        use image::DynamicImage::*;
+
+        if self.is_empty() {
+            return image;
+        }
+        match image {
+            ImageLuma8(mut buffer) => {
+                self.apply(&mut buffer);
+                ImageLuma8(buffer)
+            }
+            ImageLumaA8(mut buffer) => {
+                self.apply(&mut buffer);
+                ImageLumaA8(buffer)
+            }
+            ImageRgb8(mut buffer) => {
+                self.apply(&mut buffer);
+                ImageRgb8(buffer)
+            }
+            image => {
+                let mut buffer = image.to_rgba();
+                self.apply(&mut buffer);
+                ImageRgba8(buffer)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 9x9 black image with a white vertical line in the middle.
+    fn line() -> image::GrayImage {
+        image::GrayImage::from_fn(9, 9, |x, _| image::Luma([if x == 4 { 255 } else { 0 }]))
+    }
+
+    #[test]
+    fn test_parse_region() {
+        let region = Region::parse("200x100+10+20").unwrap();
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (10, 20, 200, 100)
+        );
+        assert!(Region::parse("200x100").is_err());
+    }
+
+    #[test]
+    fn test_parse_unsharp_mask() {
+        assert_eq!(
+            Filter::parse_unsharp_mask("1.5,0.8,3").unwrap(),
+            Filter::UnsharpMask {
+                sigma: 1.5,
+                amount: 0.8,
+                threshold: 3
+            }
+        );
+        assert!(Filter::parse_unsharp_mask("1.5").is_err());
+        assert!(Filter::parse_unsharp_mask("0,1").is_err());
+    }
+
+    #[test]
+    fn test_box_blur() {
+        let blurred = Filter::BoxBlur { radius: 1 }.apply(&line());
+        assert_eq!(blurred.get_pixel(3, 4)[0], 85);
+        assert_eq!(blurred.get_pixel(4, 4)[0], 85);
+        assert_eq!(blurred.get_pixel(1, 4)[0], 0);
+    }
+
+    #[test]
+    fn test_box_blur_huge_radius() {
+        let blurred = Filter::BoxBlur {
+            radius: u32::max_value(),
+        }
+        .apply(&line());
+        let clamped = Filter::BoxBlur { radius: 9 }.apply(&line());
+        assert_eq!(blurred.into_raw(), clamped.into_raw());
+    }
+
+    #[test]
+    fn test_blur_transparent() {
+        let mut image = image::RgbaImage::from_pixel(5, 1, image::Rgba([0, 0, 0, 0]));
+        image.put_pixel(2, 0, image::Rgba([255, 0, 0, 255]));
+        let blurred = Filter::BoxBlur { radius: 1 }.apply(&image);
+        assert_eq!(blurred.get_pixel(1, 0), &image::Rgba([255, 0, 0, 85]));
+    }
+
+    #[test]
+    fn test_region() {
+        let mut image = line();
+        let filters = Filters {
+            filters: vec![Filter::BoxBlur { radius: 1 }],
+            region: Some(Region::parse("9x4+0+0").unwrap()),
+        };
+        filters.apply(&mut image);
+        assert_eq!(image.get_pixel(3, 0)[0], 85);
+        assert_eq!(image.get_pixel(3, 8)[0], 0);
+    }
+}
@@ -0,0 +1,608 @@
+use crate::decoration::{Decoration, Styles};
+use crate::{overlay, shaping, vertical};
+use failure::Fail;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::prelude::*;
+
+#[derive(Debug, Clone, Fail)]
+pub enum FontError {
+    #[fail(display = "no face named {:?} in {}", _1, _0)]
+    FaceNotFound(String, String),
+    #[fail(display = "no font is specified and no font is embedded")]
+    NoFont,
+    #[fail(display = "{} cannot be used for text shaping", _0)]
+    Unsupported(String),
+}
+
+#[cfg(feature = "embedded-font")]
+const EMBEDDED_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+/// Whether a font is compiled into the binary, making `--font` optional.
+pub const HAS_EMBEDDED_FONT: bool = cfg!(feature = "embedded-font");
+
+/// Returns the fonts compiled into the binary in fallback order.
+pub fn embedded() -> Vec<LoadedFont> {
+    let fonts: &[&'static [u8]] = &[
+        #[cfg(feature = "embedded-font")]
+        EMBEDDED_FONT,
+    ];
+    fonts
+        .iter()
+        .map(|f| LoadedFont::new(Arc::from(*f), 0).expect("embedded font is broken"))
+        .collect()
+}
+
+/// Selects a face inside a font collection (`.ttc` / `.otc`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Face {
+    Index(usize),
+    /// Matches the PostScript name, the full name or the family name, ignoring case.
+    Name(String),
+}
+
+impl From<&str> for Face {
+    /// Parses the `FACE` of `PATH#FACE`, which is an index if it is a number.
+    fn from(s: &str) -> Face {
+        s.parse()
+            .map(Face::Index)
+            .unwrap_or_else(|_| Face::Name(s.to_owned()))
+    }
+}
+
+/// A font file and an optional face in it, written as `PATH` or `PATH#FACE`.
+///
+/// A `#` is part of the path if the whole string names an existing file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontSource {
+    pub path: String,
+    pub face: Option<Face>,
+}
+
+impl From<&str> for FontSource {
+    fn from(s: &str) -> FontSource {
+        if Path::new(s).is_file() {
+            return FontSource {
+                path: s.to_owned(),
+                face: None,
+            };
+        }
+        match s.rfind('#') {
+            Some(index) if index + 1 < s.len() => FontSource {
+                path: s[..index].to_owned(),
+                face: Some(Face::from(&s[index + 1..])),
+            },
+            _ => FontSource {
+                path: s.to_owned(),
+                face: None,
+            },
+        }
+    }
+}
+
+/// Names of a face read from its `name` table.
+#[derive(Debug, Clone, Default)]
+pub struct FaceNames {
+    pub family: Option<String>,
+    pub subfamily: Option<String>,
+    pub full_name: Option<String>,
+    pub postscript_name: Option<String>,
+    pub typographic_family: Option<String>,
+    pub typographic_subfamily: Option<String>,
+}
+
+impl FaceNames {
+    pub fn new(font: &rusttype::Font<'_>) -> FaceNames {
+        let mut names = FaceNames::default();
+        for (bytes, platform, name_id) in font.font_name_strings() {
+            let name = match name_id {
+                1 => &mut names.family,
+                2 => &mut names.subfamily,
+                4 => &mut names.full_name,
+                6 => &mut names.postscript_name,
+                16 => &mut names.typographic_family,
+                17 => &mut names.typographic_subfamily,
+                _ => continue,
+            };
+            if name.is_none() {
+                *name = decode_name(bytes, platform);
+            }
+        }
+        names
+    }
+
+    /// Returns the family grouping every weight and style of the face.
+    pub fn preferred_family(&self) -> Option<&str> {
+        self.typographic_family
+            .as_deref()
+            .or(self.family.as_deref())
+    }
+
+    /// Returns the weight and style of the face within its preferred family.
+    pub fn preferred_subfamily(&self) -> Option<&str> {
+        self.typographic_subfamily
+            .as_deref()
+            .or(self.subfamily.as_deref())
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        [&self.postscript_name, &self.full_name, &self.family]
+            .iter()
+            .filter_map(|n| n.as_ref())
+            .any(|n| n.eq_ignore_ascii_case(query))
+    }
+}
+
+fn decode_name(
+    bytes: &[u8],
+    platform: Option<stb_truetype::PlatformEncodingLanguageId>,
+) -> Option<String> {
+    use stb_truetype::PlatformEncodingLanguageId::*;
+
+    match platform? {
+        Unicode(..) | Microsoft(..) => {
+            let units = bytes
+                .chunks(2)
+                .filter(|c| c.len() == 2)
+                .map(|c| u16::from(c[0]) << 8 | u16::from(c[1]))
+                .collect::<Vec<_>>();
+            String::from_utf16(&units).ok()
+        }
+        Mac(..) if bytes.is_ascii() => Some(bytes.iter().map(|b| *b as char).collect()),
+        _ => None,
+    }
+}
+
+async fn read_file<P>(font_path: P) -> Result<Arc<[u8]>, failure::Error>
+where
+    P: AsRef<Path>,
+{
+    let mut font_file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .open(font_path)
+        .await?;
+    let mut font = Vec::new();
+    font_file.read_to_end(&mut font).await?;
+    drop(font_file);
+    Ok(Arc::from(font))
+}
+
+/// A face of a font file with the bytes it was parsed from, which the shaper reads.
+#[derive(Clone)]
+pub struct LoadedFont {
+    pub font: rusttype::Font<'static>,
+    data: Arc<[u8]>,
+    index: u32,
+}
+
+impl LoadedFont {
+    fn new(data: Arc<[u8]>, index: usize) -> Result<LoadedFont, failure::Error> {
+        let font = rusttype::FontCollection::from_bytes(data.clone())?.font_at(index)?;
+        Ok(LoadedFont {
+            font,
+            data,
+            index: index as u32,
+        })
+    }
+
+    fn has_glyph(&self, c: char) -> bool {
+        self.font.glyph(c).id().0 != 0
+    }
+
+    /// Whether `c` is drawn without advancing in this font, as combining marks are.
+    fn is_mark(&self, c: char) -> bool {
+        self.has_glyph(c)
+            && self
+                .font
+                .glyph(c)
+                .scaled(rusttype::Scale::uniform(1.0))
+                .h_metrics()
+                .advance_width
+                == 0.0
+    }
+
+    /// Returns the shaped glyphs of a run and the scale from font units to `scale` as `(x, y)`.
+    fn shape(
+        &self,
+        text: &str,
+        rtl: bool,
+        scale: rusttype::Scale,
+        features: &[rustybuzz::Feature],
+    ) -> (Vec<shaping::ShapedGlyph>, (f32, f32)) {
+        let v_metrics = self.font.v_metrics_unscaled();
+        let y = scale.y / (v_metrics.ascent - v_metrics.descent);
+        let glyphs = shaping::shape(&self.data, self.index, text, rtl, features)
+            .expect("font was checked when loaded");
+        (glyphs, (y * scale.x / scale.y, y))
+    }
+}
+
+/// Loads the face selected by `source`.
+///
+/// If `source` does not select one, `default_face` is used for collections
+/// holding more than one face and the only face is used otherwise.
+pub async fn load(
+    source: &FontSource,
+    default_face: Option<&Face>,
+) -> Result<LoadedFont, failure::Error> {
+    let data = read_file(&source.path).await?;
+    let collection = rusttype::FontCollection::from_bytes(data.clone())?;
+    let is_collection = collection.font_at(1).is_ok();
+
+    let face = match (&source.face, default_face) {
+        (Some(face), _) => Some(face),
+        (None, Some(face)) if is_collection => Some(face),
+        _ => None,
+    };
+
+    let index = match face {
+        None => 0,
+        Some(Face::Index(index)) => *index,
+        Some(Face::Name(name)) => {
+            let mut found = None;
+            for (index, font) in collection.into_fonts().enumerate() {
+                if FaceNames::new(&font?).matches(name) {
+                    found = Some(index);
+                    break;
+                }
+            }
+            found.ok_or_else(|| FontError::FaceNotFound(source.path.clone(), name.clone()))?
+        }
+    };
+
+    let font = LoadedFont::new(data, index)?;
+    if rustybuzz::Face::from_slice(&font.data, font.index).is_none() {
+        return Err(FontError::Unsupported(source.path.clone()).into());
+    }
+    Ok(font)
+}
+
+/// Lists the names of every face in a font file.
+pub async fn faces<P>(font_path: P) -> Result<Vec<FaceNames>, failure::Error>
+where
+    P: AsRef<Path>,
+{
+    let collection = rusttype::FontCollection::from_bytes(read_file(font_path).await?)?;
+    let mut faces = Vec::new();
+    for font in collection.into_fonts() {
+        faces.push(FaceNames::new(&font?));
+    }
+    Ok(faces)
+}
+
+/// A positioned glyph, optionally turned sideways for vertical text.
+#[derive(Clone)]
+pub struct Glyph {
+    pub positioned: rusttype::PositionedGlyph<'static>,
+    /// If set, `positioned` is laid out at the origin and drawn rotated 90 degrees clockwise
+    /// with its origin moved to this point.
+    pub sideways: Option<rusttype::Point<i32>>,
+}
+
+impl Glyph {
+    fn upright(positioned: rusttype::PositionedGlyph<'static>) -> Glyph {
+        Glyph {
+            positioned,
+            sideways: None,
+        }
+    }
+
+    pub fn pixel_bounding_box(&self) -> Option<rusttype::Rect<i32>> {
+        let bb = self.positioned.pixel_bounding_box()?;
+        Some(match self.sideways {
+            Some(p) => rusttype::Rect {
+                min: rusttype::point(p.x - bb.max.y, p.y + bb.min.x),
+                max: rusttype::point(p.x - bb.min.y, p.y + bb.max.x),
+            },
+            None => bb,
+        })
+    }
+
+    /// Calls `o` with the position and coverage of each pixel of the glyph.
+    pub fn draw<O>(&self, mut o: O)
+    where
+        O: FnMut(i32, i32, f32),
+    {
+        if let Some(bb) = self.positioned.pixel_bounding_box() {
+            let sideways = self.sideways;
+            self.positioned.draw(|gx, gy, gv| {
+                let x = gx as i32 + bb.min.x;
+                let y = gy as i32 + bb.min.y;
+                match sideways {
+                    Some(p) => o(p.x - y - 1, p.y + x, gv),
+                    None => o(x, y, gv),
+                }
+            });
+        }
+    }
+}
+
+/// Fonts tried in order for each glyph.
+///
+/// The first font containing a glyph for a character draws it.
+/// Characters missing from every font are drawn with the primary font.
+#[derive(Clone)]
+pub struct FontChain {
+    fonts: Vec<LoadedFont>,
+    features: Vec<rustybuzz::Feature>,
+}
+
+impl FontChain {
+    pub fn new(fonts: Vec<LoadedFont>, features: Vec<rustybuzz::Feature>) -> FontChain {
+        assert!(!fonts.is_empty(), "font chain must have at least one font");
+        FontChain { fonts, features }
+    }
+
+    pub fn primary(&self) -> &rusttype::Font<'static> {
+        &self.fonts[0].font
+    }
+
+    pub fn v_metrics(&self, scale: rusttype::Scale) -> rusttype::VMetrics {
+        self.primary().v_metrics(scale)
+    }
+
+    fn find_font(&self, c: char) -> Option<&rusttype::Font<'static>> {
+        self.fonts.iter().find(|f| f.has_glyph(c)).map(|f| &f.font)
+    }
+
+    fn font_for(&self, c: char) -> &rusttype::Font<'static> {
+        self.find_font(c).unwrap_or_else(|| self.primary())
+    }
+
+    /// Splits text into runs drawn with the same font of the chain.
+    ///
+    /// Combining marks stay with their base character if its font has them.
+    fn segments(&self, text: &str) -> Vec<(usize, String)> {
+        let mut segments: Vec<(usize, String)> = Vec::new();
+        for c in text.chars().filter(|c| !c.is_control()) {
+            match segments.last_mut() {
+                Some((index, segment)) if self.fonts[*index].is_mark(c) => segment.push(c),
+                _ => {
+                    let index = self.fonts.iter().position(|f| f.has_glyph(c)).unwrap_or(0);
+                    match segments.last_mut() {
+                        Some((last, segment)) if *last == index => segment.push(c),
+                        _ => segments.push((index, c.to_string())),
+                    }
+                }
+            }
+        }
+        segments
+    }
+
+    /// Lays out a shaped line with its top-left corner at the origin.
+    ///
+    /// Mixed-direction text is reordered by the Unicode bidi algorithm.
+    /// `tracking` pixels are added after every glyph that advances.
+    /// Returns the positioned glyphs and the advance width of the line.
+    pub fn layout(&self, text: &str, scale: rusttype::Scale, tracking: f32) -> (Vec<Glyph>, f32) {
+        let baseline = self.v_metrics(scale).ascent;
+        let mut caret = 0.0;
+        let mut glyphs = Vec::new();
+
+        for (range, rtl) in shaping::visual_runs(text) {
+            let mut segments = self.segments(&text[range]);
+            if rtl {
+                segments.reverse();
+            }
+
+            for (index, segment) in segments {
+                let font = &self.fonts[index];
+                let (shaped, (x_scale, y_scale)) = font.shape(&segment, rtl, scale, &self.features);
+                for g in shaped {
+                    let position = rusttype::point(
+                        caret + g.x_offset as f32 * x_scale,
+                        baseline - g.y_offset as f32 * y_scale,
+                    );
+                    let glyph = font
+                        .font
+                        .glyph(rusttype::GlyphId(g.id))
+                        .scaled(scale)
+                        .positioned(position);
+                    caret += g.x_advance as f32 * x_scale;
+                    if g.x_advance != 0 {
+                        caret += tracking;
+                    }
+                    glyphs.push(Glyph::upright(glyph));
+                }
+            }
+        }
+
+        (glyphs, caret)
+    }
+
+    /// Lays out a column of vertical text `scale.x` wide with its top-left corner at the origin.
+    ///
+    /// Punctuation marks use their vertical forms if a font has them and are turned sideways
+    /// or moved to the upper right of their cells otherwise. Small kana move to the upper right.
+    ///
+    /// `tracking` pixels are added after every character.
+    /// Returns the positioned glyphs and the advance height of the column.
+    pub fn layout_vertical(
+        &self,
+        text: &str,
+        scale: rusttype::Scale,
+        tracking: f32,
+    ) -> (Vec<Glyph>, f32) {
+        let v_metrics = self.v_metrics(scale);
+        let cell = scale.y;
+        let mut caret = 0.0f32;
+
+        let glyphs = text
+            .chars()
+            .filter(|c| !c.is_control())
+            .map(|c| {
+                let form = vertical::vertical_form(c)
+                    .and_then(|f| self.find_font(f).map(|font| (f, font)));
+
+                if form.is_none() && vertical::is_sideways(c) {
+                    let glyph = self.font_for(c).glyph(c).scaled(scale);
+                    let advance_width = glyph.h_metrics().advance_width;
+                    let pivot = rusttype::point(
+                        ((scale.x - cell) / 2.0 - v_metrics.descent).round() as i32,
+                        caret.round() as i32,
+                    );
+                    caret += advance_width + tracking;
+                    return Glyph {
+                        positioned: glyph.positioned(rusttype::point(0.0, 0.0)),
+                        sideways: Some(pivot),
+                    };
+                }
+
+                let (c, font) = form.unwrap_or_else(|| (c, self.font_for(c)));
+                let glyph = font.glyph(c).scaled(scale);
+                let advance_width = glyph.h_metrics().advance_width;
+                let (dx, dy) = if vertical::is_small_kana(c) {
+                    (cell * 0.1, -cell * 0.1)
+                } else if form.is_none() && vertical::is_corner_punctuation(c) {
+                    (cell * 0.6, -cell * 0.6)
+                } else {
+                    (0.0, 0.0)
+                };
+                let position = rusttype::point(
+                    (scale.x - advance_width) / 2.0 + dx,
+                    caret + v_metrics.ascent + dy,
+                );
+                caret += cell + tracking;
+                Glyph::upright(glyph.positioned(position))
+            })
+            .collect();
+
+        (glyphs, caret)
+    }
+}
+
+/// The fonts used to draw a text, selected by decoration level.
+pub struct Fonts {
+    default: FontChain,
+    levels: HashMap<Decoration, FontChain>,
+}
+
+impl Fonts {
+    pub async fn load(
+        default_sources: &[FontSource],
+        default_face: Option<&Face>,
+        styles: &Styles,
+        features: &[rustybuzz::Feature],
+    ) -> Result<Fonts, failure::Error> {
+        let mut fonts = Vec::new();
+        for source in default_sources {
+            fonts.push(load(source, default_face).await?);
+        }
+        fonts.extend(embedded());
+        if fonts.is_empty() {
+            return Err(FontError::NoFont.into());
+        }
+        let default = FontChain::new(fonts, features.to_vec());
+
+        let mut levels = HashMap::new();
+        for (decoration, font_path) in styles.font_paths() {
+            log::info!("font path ({:?}): {}", decoration, font_path);
+            let source = FontSource::from(font_path);
+            let mut fonts = vec![load(&source, default_face).await?];
+            fonts.extend(default.fonts.iter().cloned());
+            levels.insert(decoration, FontChain::new(fonts, features.to_vec()));
+        }
+        Ok(Fonts { default, levels })
+    }
+
+    pub fn get(&self, decoration: Decoration) -> &FontChain {
+        self.levels.get(&decoration).unwrap_or(&self.default)
+    }
+}
+
+/// Returns the union of the pixel bounding boxes of `glyphs`, or `None` if nothing is drawn.
+pub fn pixel_bounds(glyphs: &[Glyph]) -> Option<rusttype::Rect<i32>> {
+    glyphs.iter().filter_map(Glyph::pixel_bounding_box).fold(
+        None,
+        |bounds: Option<rusttype::Rect<i32>>, bb| {
+            Some(match bounds {
+                Some(r) => rusttype::Rect {
+                    min: rusttype::point(r.min.x.min(bb.min.x), r.min.y.min(bb.min.y)),
+                    max: rusttype::point(r.max.x.max(bb.max.x), r.max.y.max(bb.max.y)),
+                },
+                None => bb,
+            })
+        },
+    )
+}
+
+/// Draws glyphs laid out by `FontChain` with their origin at `(x, y)`,
+/// with the color at each canvas pixel given by `color_at`.
+///
+/// Each pixel is blended over the canvas by `overlay::blend` with the alpha of the color
+/// scaled by the glyph coverage.
+pub fn fill_glyphs_mut<I, C>(canvas: &mut I, color_at: C, x: i32, y: i32, glyphs: &[Glyph])
+where
+    I: image::GenericImage,
+    I::Pixel: image::Pixel<Subpixel = u8>,
+    C: Fn(i32, i32) -> I::Pixel,
+{
+    use image::Pixel;
+
+    let (width, height) = canvas.dimensions();
+
+    for glyph in glyphs {
+        glyph.draw(|gx, gy, gv| {
+            let image_x = gx + x;
+            let image_y = gy + y;
+
+            if image_x >= 0 && image_x < width as i32 && image_y >= 0 && image_y < height as i32 {
+                let mut color = color_at(image_x, image_y);
+                if let Some(alpha) = color.channels_mut().last_mut() {
+                    *alpha = (f32::from(*alpha) * gv).round() as u8;
+                }
+                let mut pixel = canvas.get_pixel(image_x as u32, image_y as u32);
+                overlay::blend(&mut pixel, &color);
+                canvas.put_pixel(image_x as u32, image_y as u32, pixel);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_font_source_from_str() {
+        let source = FontSource::from("fonts/NotoSans-Regular.ttf");
+        assert_eq!(source.path, "fonts/NotoSans-Regular.ttf");
+        assert_eq!(source.face, None);
+    }
+
+    #[test]
+    fn test_font_source_from_str_index() {
+        let source = FontSource::from("NotoSansCJK.ttc#2");
+        assert_eq!(source.path, "NotoSansCJK.ttc");
+        assert_eq!(source.face, Some(Face::Index(2)));
+    }
+
+    #[test]
+    fn test_font_source_from_str_name() {
+        let source = FontSource::from("NotoSansCJK.ttc#NotoSansCJKjp-Bold");
+        assert_eq!(source.path, "NotoSansCJK.ttc");
+        assert_eq!(
+            source.face,
+            Some(Face::Name("NotoSansCJKjp-Bold".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_font_source_from_str_trailing_hash() {
+        let source = FontSource::from("fonts/#");
+        assert_eq!(source.path, "fonts/#");
+        assert_eq!(source.face, None);
+    }
+
+    #[test]
+    fn test_font_source_from_str_existing_file_with_hash() {
+        let path = std::env::temp_dir().join(format!("sanjo-{}-My#Font.ttf", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let path_str = path.to_str().unwrap();
+        let source = FontSource::from(path_str);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(source.path, path_str);
+        assert_eq!(source.face, None);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::color::Color;
+use crate::layout::Gravity;
+use crate::MAX_CANVAS_SIDE;
+use failure::Fail;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Fail)]
+pub enum FrameError {
+    #[fail(
+        display = "invalid sides: {} (expected ALL, VERTICAL,HORIZONTAL, TOP,HORIZONTAL,BOTTOM or TOP,RIGHT,BOTTOM,LEFT)",
+        _0
+    )]
+    InvalidSides(String),
+    #[fail(display = "invalid aspect ratio: {} (expected WIDTH:HEIGHT)", _0)]
+    InvalidAspectRatio(String),
+    #[fail(display = "the framed image is larger than {}x{}", _0, _0)]
+    TooLarge(u32),
+}
+
+/// Widths in pixels of the four sides of a box.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sides {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Sides {
+    /// Parses one to four comma separated widths in the order of CSS margins.
+    pub fn parse(s: &str) -> Result<Sides, FrameError> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| FrameError::InvalidSides(s.to_owned()))?;
+
+        let (top, right, bottom, left) = match values.as_slice() {
+            [all] => (*all, *all, *all, *all),
+            [vertical, horizontal] => (*vertical, *horizontal, *vertical, *horizontal),
+            [top, horizontal, bottom] => (*top, *horizontal, *bottom, *horizontal),
+            [top, right, bottom, left] => (*top, *right, *bottom, *left),
+            _ => return Err(FrameError::InvalidSides(s.to_owned())),
+        };
+        Ok(Sides {
+            top,
+            right,
+            bottom,
+            left,
+        })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Sides::default()
+    }
+}
+
+/// Parses an aspect ratio `WIDTH:HEIGHT` such as `16:9`.
+pub fn parse_aspect_ratio(s: &str) -> Result<(u32, u32), FrameError> {
+    let mut split = s.splitn(2, ':').map(|v| v.trim().parse::<u32>().ok());
+    match (split.next().flatten(), split.next().flatten()) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(FrameError::InvalidAspectRatio(s.to_owned())),
+    }
+}
+
+/// Space added around the image: padding, then an extension to an aspect ratio, then a border.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub padding: Sides,
+    /// Fills the padding and the extension.
+    pub padding_color: Color,
+    pub aspect_ratio: Option<(u32, u32)>,
+    /// Where the image is anchored in the extended canvas. Centered by default.
+    pub gravity: Option<Gravity>,
+    pub border: Sides,
+    pub border_color: Color,
+}
+
+impl Default for Frame {
+    fn default() -> Frame {
+        Frame {
+            padding: Sides::default(),
+            padding_color: Color::white(),
+            aspect_ratio: None,
+            gravity: None,
+            border: Sides::default(),
+            border_color: Color::black(),
+        }
+    }
+}
+
+impl Frame {
+    pub fn is_empty(&self) -> bool {
+        self.padding.is_zero() && self.aspect_ratio.is_none() && self.border.is_zero()
+    }
+
+    /// Adds the frame around `image`, failing if a side would be longer than `MAX_CANVAS_SIDE`.
+    pub fn apply<P>(
+        &self,
+        image: image::ImageBuffer<P, Vec<u8>>,
+    ) -> Result<image::ImageBuffer<P, Vec<u8>>, FrameError>
+    where
+        P: image::Pixel<Subpixel = u8> + From<Color> + 'static,
+    {
+        let mut image = image;
+        if !self.padding.is_zero() {
+            image = surround(&image, self.padding, self.padding_color.into())?;
+        }
+
+        if let Some((ratio_width, ratio_height)) = self.aspect_ratio {
+            let (width, height) = (u64::from(image.width()), u64::from(image.height()));
+            let (ratio_width, ratio_height) = (u64::from(ratio_width), u64::from(ratio_height));
+            let extended = |side: u64, numerator: u64, denominator: u64| {
+                side.checked_mul(numerator)
+                    .map(|product| product.div_ceil(denominator))
+            };
+            let size = if width * ratio_height > height * ratio_width {
+                (Some(width), extended(width, ratio_height, ratio_width))
+            } else {
+                (extended(height, ratio_width, ratio_height), Some(height))
+            };
+            let size = checked_size(size.0, size.1)?;
+
+            let (x, y) = anchor(self.gravity, size, image.dimensions());
+            let mut extended =
+                image::ImageBuffer::from_pixel(size.0, size.1, self.padding_color.into());
+            image::imageops::replace(&mut extended, &image, x, y);
+            image = extended;
+        }
+
+        if !self.border.is_zero() {
+            image = surround(&image, self.border, self.border_color.into())?;
+        }
+        Ok(image)
+    }
+
+    /// Frames a decoded image of any color type, which becomes RGBA or grayscale with alpha
+    /// so that the colors may be transparent.
+    pub fn apply_dynamic(
+        &self,
+        image: image::DynamicImage,
+    ) -> Result<image::DynamicImage, FrameError> {
+        use image::DynamicImage::*;
+
+        if self.is_empty() {
+            return Ok(image);
+        }
+        Ok(match image {
+            ImageLuma8(_) | ImageLumaA8(_) => ImageLumaA8(self.apply(image.to_luma_alpha())?),
+            image => ImageRgba8(self.apply(image.to_rgba())?),
+        })
+    }
+}
+
+/// Returns the top-left corner of an image of `size` in an extended canvas of `canvas_size`,
+/// flush with the edge of `gravity` and centered along it.
+fn anchor(gravity: Option<Gravity>, canvas_size: (u32, u32), size: (u32, u32)) -> (u32, u32) {
+    let (right, bottom) = (canvas_size.0 - size.0, canvas_size.1 - size.1);
+    let (center, middle) = (right / 2, bottom / 2);
+    match gravity.unwrap_or(Gravity::Centered) {
+        Gravity::Centered => (center, middle),
+        Gravity::UpperCentered => (center, 0),
+        Gravity::LowerCentered => (center, bottom),
+        Gravity::LeftCentered => (0, middle),
+        Gravity::RightCentered => (right, middle),
+    }
+}
+
+/// Returns `image` with `sides` of `color` added around it.
+fn surround<P>(
+    image: &image::ImageBuffer<P, Vec<u8>>,
+    sides: Sides,
+    color: P,
+) -> Result<image::ImageBuffer<P, Vec<u8>>, FrameError>
+where
+    P: image::Pixel<Subpixel = u8> + 'static,
+{
+    let add = |side: u32, before: u32, after: u32| {
+        side.checked_add(before)
+            .and_then(|side| side.checked_add(after))
+            .map(u64::from)
+    };
+    let (width, height) = checked_size(
+        add(image.width(), sides.left, sides.right),
+        add(image.height(), sides.top, sides.bottom),
+    )?;
+    let mut surrounded = image::ImageBuffer::from_pixel(width, height, color);
+    image::imageops::replace(&mut surrounded, image, sides.left, sides.top);
+    Ok(surrounded)
+}
+
+/// Returns the size of a framed image, failing if a side overflowed or exceeds `MAX_CANVAS_SIDE`.
+fn checked_size(width: Option<u64>, height: Option<u64>) -> Result<(u32, u32), FrameError> {
+    let side = |side: Option<u64>| {
+        side.and_then(|side| u32::try_from(side).ok())
+            .filter(|side| *side <= MAX_CANVAS_SIDE)
+    };
+    match (side(width), side(height)) {
+        (Some(width), Some(height)) => Ok((width, height)),
+        _ => Err(FrameError::TooLarge(MAX_CANVAS_SIDE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> image::RgbaImage {
+        image::RgbaImage::from_pixel(4, 2, image::Rgba([255, 0, 0, 255]))
+    }
+
+    #[test]
+    fn test_parse_sides() {
+        let sides = Sides::parse("1,2,3").unwrap();
+        assert_eq!(
+            (sides.top, sides.right, sides.bottom, sides.left),
+            (1, 2, 3, 2)
+        );
+        assert_eq!(Sides::parse("5").unwrap().left, 5);
+        assert!(Sides::parse("1,2,3,4,5").is_err());
+        assert!(Sides::parse("wide").is_err());
+    }
+
+    #[test]
+    fn test_parse_aspect_ratio() {
+        assert_eq!(parse_aspect_ratio("16:9").unwrap(), (16, 9));
+        assert!(parse_aspect_ratio("16").is_err());
+        assert!(parse_aspect_ratio("0:1").is_err());
+    }
+
+    #[test]
+    fn test_padding_and_border() {
+        let frame = Frame {
+            padding: Sides::parse("1").unwrap(),
+            padding_color: Color::clear(),
+            border: Sides::parse("0,2").unwrap(),
+            ..Frame::default()
+        };
+        let framed = frame.apply(image()).unwrap();
+        assert_eq!(framed.dimensions(), (10, 4));
+        assert_eq!(framed.get_pixel(0, 0), &image::Rgba([0, 0, 0, 255]));
+        assert_eq!(framed.get_pixel(2, 0), &image::Rgba([0, 0, 0, 0]));
+        assert_eq!(framed.get_pixel(3, 1), &image::Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_aspect_ratio() {
+        let frame = Frame {
+            aspect_ratio: Some((1, 1)),
+            gravity: Some(Gravity::UpperCentered),
+            ..Frame::default()
+        };
+        let framed = frame.apply(image()).unwrap();
+        assert_eq!(framed.dimensions(), (4, 4));
+        assert_eq!(framed.get_pixel(0, 0)[1], 0);
+        assert_eq!(framed.get_pixel(0, 3)[1], 255);
+
+        let frame = Frame {
+            aspect_ratio: Some((3, 1)),
+            ..Frame::default()
+        };
+        let framed = frame.apply(image()).unwrap();
+        assert_eq!(framed.dimensions(), (6, 2));
+        assert_eq!(framed.get_pixel(0, 0)[1], 255);
+        assert_eq!(framed.get_pixel(1, 0)[1], 0);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let frame = Frame {
+            padding: Sides::parse(&u32::max_value().to_string()).unwrap(),
+            ..Frame::default()
+        };
+        assert!(frame.apply(image()).is_err());
+
+        let frame = Frame {
+            aspect_ratio: Some((u32::max_value(), 1)),
+            ..Frame::default()
+        };
+        assert!(frame.apply(image()).is_err());
+
+        let frame = Frame {
+            border: Sides::parse("0,4998").unwrap(),
+            ..Frame::default()
+        };
+        assert_eq!(frame.apply(image()).unwrap().width(), MAX_CANVAS_SIDE);
+        let frame = Frame {
+            border: Sides::parse("0,4999").unwrap(),
+            ..Frame::default()
+        };
+        assert!(frame.apply(image()).is_err());
+    }
+
+    #[test]
+    fn test_aspect_gravity_flush() {
+        let image = image::RgbaImage::from_pixel(100, 60, image::Rgba([255, 0, 0, 255]));
+        let frame = |gravity| Frame {
+            aspect_ratio: Some((1, 1)),
+            gravity: Some(gravity),
+            ..Frame::default()
+        };
+
+        let framed = frame(Gravity::UpperCentered).apply(image.clone()).unwrap();
+        assert_eq!(framed.dimensions(), (100, 100));
+        assert_eq!(framed.get_pixel(50, 0)[1], 0);
+        assert_eq!(framed.get_pixel(50, 60)[1], 255);
+
+        let framed = frame(Gravity::LowerCentered).apply(image.clone()).unwrap();
+        assert_eq!(framed.get_pixel(50, 39)[1], 255);
+        assert_eq!(framed.get_pixel(50, 40)[1], 0);
+        assert_eq!(framed.get_pixel(50, 99)[1], 0);
+
+        let image = image::RgbaImage::from_pixel(60, 100, image::Rgba([255, 0, 0, 255]));
+        let framed = frame(Gravity::LeftCentered).apply(image.clone()).unwrap();
+        assert_eq!(framed.get_pixel(0, 50)[1], 0);
+        assert_eq!(framed.get_pixel(60, 50)[1], 255);
+        let framed = frame(Gravity::RightCentered).apply(image).unwrap();
+        assert_eq!(framed.get_pixel(39, 50)[1], 255);
+        assert_eq!(framed.get_pixel(99, 50)[1], 0);
+    }
+}
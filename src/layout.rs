@@ -0,0 +1,459 @@
+use crate::decoration::{DecoratedString, Decoration, Styles};
+use crate::font::{self, Fonts};
+use clap::arg_enum;
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum WritingMode {
+        Horizontal,
+        Vertical,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum Gravity {
+        UpperCentered,
+        LeftCentered,
+        LowerCentered,
+        RightCentered,
+        Centered,
+    }
+}
+
+impl Gravity {
+    /// Returns the top-left corner of a box of `size` placed on a canvas of `canvas_size`.
+    pub fn anchor(self, canvas_size: (u32, u32), size: (i32, i32)) -> (i32, i32) {
+        let (canvas_width, canvas_height) = (canvas_size.0 as i32, canvas_size.1 as i32);
+        let margin = ((size.1 as f32 / 16.0) + 0.5) as i32;
+        let centered_x = (canvas_width - size.0) / 2;
+        let centered_y = (canvas_height - size.1) / 2;
+
+        match self {
+            Gravity::UpperCentered => (centered_x, margin),
+            Gravity::LeftCentered => (margin, centered_y),
+            Gravity::LowerCentered => (centered_x, canvas_height - size.1 - margin),
+            Gravity::RightCentered => (canvas_width - size.0, centered_y),
+            Gravity::Centered => (centered_x, centered_y),
+        }
+    }
+}
+
+/// How lines and characters are spaced.
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutOptions {
+    pub writing_mode: WritingMode,
+    /// The distance between lines as a multiple of the font height.
+    pub line_height: f32,
+    /// Space added after each character in thousandths of the font height.
+    pub letter_spacing: f32,
+    /// The distance a blank line takes as a multiple of the line height.
+    pub paragraph_spacing: f32,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> LayoutOptions {
+        LayoutOptions {
+            writing_mode: WritingMode::Horizontal,
+            line_height: 1.0,
+            letter_spacing: 0.0,
+            paragraph_spacing: 1.0,
+        }
+    }
+}
+
+/// A laid out line of a text.
+#[derive(Clone)]
+pub struct TextBox {
+    pub decoration: Decoration,
+    pub glyphs: Vec<font::Glyph>,
+    /// Where the origin of `glyphs` is placed on the canvas.
+    pub origin: (i32, i32),
+    /// The ink bounds of the line on the canvas as `(x, y, width, height)`.
+    pub rect: (i32, i32, i32, i32),
+}
+
+/// Lays out lines centered on each other, or as columns from right to left in vertical mode.
+///
+/// The block is placed by the tight ink bounds of its glyphs, extended by `shadow_offset`
+/// on every side: its top-left corner goes to `input_position` if given,
+/// or is anchored by `gravity` (`Centered` by default).
+#[allow(clippy::too_many_arguments)]
+pub fn textboxes<T, U>(
+    input_position: Option<T>,
+    gravity: Option<Gravity>,
+    texts: U,
+    canvas_size: (u32, u32),
+    fonts: &Fonts,
+    styles: &Styles,
+    height: u32,
+    shadow_offset: i32,
+    options: &LayoutOptions,
+) -> Result<Vec<TextBox>, failure::Error>
+where
+    T: Into<(u32, u32)> + Copy,
+    U: IntoIterator<Item = DecoratedString>,
+{
+    let lines = texts
+        .into_iter()
+        .map(|l| {
+            let font = fonts.get(l.decoration);
+            let scale_factor = styles.get(l.decoration).scale_factor;
+            let scale = rusttype::Scale::uniform(height as f32 * scale_factor);
+
+            let mut line_height = scale.y * options.line_height;
+            if l.body.is_empty() {
+                line_height *= options.paragraph_spacing;
+            }
+            let tracking = scale.y * options.letter_spacing / 1000.0;
+            let (glyphs, _) = match options.writing_mode {
+                WritingMode::Horizontal => font.layout(&l.body, scale, tracking),
+                WritingMode::Vertical => font.layout_vertical(&l.body, scale, tracking),
+            };
+            let line_height = line_height.round() as i32;
+            let ink = font::pixel_bounds(&glyphs);
+            (l.decoration, glyphs, ink, line_height)
+        })
+        .collect::<Vec<_>>();
+
+    let mut textboxes = match options.writing_mode {
+        WritingMode::Horizontal => horizontal(lines),
+        WritingMode::Vertical => vertical(lines),
+    };
+
+    let (left, top, width, height) = bounds(&textboxes);
+    let block_size = (width + shadow_offset * 2, height + shadow_offset * 2);
+    let (x, y) = place(input_position, gravity, canvas_size, block_size);
+    let (dx, dy) = (x + shadow_offset - left, y + shadow_offset - top);
+
+    for textbox in &mut textboxes {
+        textbox.origin = (textbox.origin.0 + dx, textbox.origin.1 + dy);
+        textbox.rect = (
+            textbox.rect.0 + dx,
+            textbox.rect.1 + dy,
+            textbox.rect.2,
+            textbox.rect.3,
+        );
+    }
+
+    Ok(textboxes)
+}
+
+/// Returns the union of the ink bounds of `textboxes` as `(x, y, width, height)`.
+pub fn bounds(textboxes: &[TextBox]) -> (i32, i32, i32, i32) {
+    let left = textboxes.iter().map(|t| t.rect.0).min().unwrap_or(0);
+    let top = textboxes.iter().map(|t| t.rect.1).min().unwrap_or(0);
+    let right = textboxes
+        .iter()
+        .map(|t| t.rect.0 + t.rect.2)
+        .max()
+        .unwrap_or(0);
+    let bottom = textboxes
+        .iter()
+        .map(|t| t.rect.1 + t.rect.3)
+        .max()
+        .unwrap_or(0);
+    (left, top, right - left, bottom - top)
+}
+
+/// Returns the top-left corner of a box of `size`: `input_position` if given,
+/// or the anchor of `gravity` (`Centered` by default).
+pub fn place<T>(
+    input_position: Option<T>,
+    gravity: Option<Gravity>,
+    canvas_size: (u32, u32),
+    size: (i32, i32),
+) -> (i32, i32)
+where
+    T: Into<(u32, u32)>,
+{
+    match input_position {
+        Some(position) => {
+            let position = position.into();
+            (position.0 as i32, position.1 as i32)
+        }
+        None => {
+            log::info!("canvas size: {:?}", canvas_size);
+            log::info!("block size: {:?}", size);
+            gravity
+                .unwrap_or(Gravity::Centered)
+                .anchor(canvas_size, size)
+        }
+    }
+}
+
+/// Returns the size of the bounding box of a box of `size` rotated by `degrees`.
+pub fn rotated_size(size: (i32, i32), degrees: f32) -> (i32, i32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (size.0 as f32, size.1 as f32);
+    (
+        (width * cos.abs() + height * sin.abs() - 1e-3).ceil() as i32,
+        (width * sin.abs() + height * cos.abs() - 1e-3).ceil() as i32,
+    )
+}
+
+type Line = (
+    Decoration,
+    Vec<font::Glyph>,
+    Option<rusttype::Rect<i32>>,
+    i32,
+);
+
+/// Stacks lines downwards, each centered within the widest line.
+fn horizontal(lines: Vec<Line>) -> Vec<TextBox> {
+    let block_width = lines
+        .iter()
+        .filter_map(|(_, _, ink, _)| ink.map(|r| r.width()))
+        .max()
+        .unwrap_or(0);
+
+    let mut top = 0;
+    lines
+        .into_iter()
+        .map(|(decoration, glyphs, ink, line_height)| {
+            let textbox = match ink {
+                Some(ink) => {
+                    let x = (block_width - ink.width()) / 2;
+                    TextBox {
+                        decoration,
+                        glyphs,
+                        origin: (x - ink.min.x, top),
+                        rect: (x, top + ink.min.y, ink.width(), ink.height()),
+                    }
+                }
+                None => TextBox {
+                    decoration,
+                    glyphs,
+                    origin: (block_width / 2, top),
+                    rect: (block_width / 2, top, 0, line_height),
+                },
+            };
+            top += line_height;
+            textbox
+        })
+        .collect()
+}
+
+/// Places columns from right to left, aligned at the top.
+fn vertical(lines: Vec<Line>) -> Vec<TextBox> {
+    let mut right = 0;
+    lines
+        .into_iter()
+        .map(|(decoration, glyphs, ink, line_height)| {
+            let left = right - line_height;
+            let textbox = match ink {
+                Some(ink) => TextBox {
+                    decoration,
+                    glyphs,
+                    origin: (left, 0),
+                    rect: (left + ink.min.x, ink.min.y, ink.width(), ink.height()),
+                },
+                None => TextBox {
+                    decoration,
+                    glyphs,
+                    origin: (left, 0),
+                    rect: (left, 0, line_height, 0),
+                },
+            };
+            right = left;
+            textbox
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "embedded-font"))]
+mod tests {
+    use super::*;
+    use crate::decoration;
+
+    async fn fonts() -> Fonts {
+        Fonts::load(&[], None, &Styles::default(), &[])
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rotated_size() {
+        assert_eq!(rotated_size((100, 20), 0.0), (100, 20));
+        assert_eq!(rotated_size((100, 20), 90.0), (20, 100));
+        assert_eq!(rotated_size((100, 20), -180.0), (100, 20));
+        assert_eq!(rotated_size((10, 10), 45.0), (15, 15));
+    }
+
+    #[tokio::test]
+    async fn test_kerning() {
+        let fonts = fonts().await;
+        let chain = fonts.get(Decoration::Normal);
+        let scale = rusttype::Scale::uniform(48.0);
+
+        let (kerned, _) = chain.layout("AV", scale, 0.0);
+        let (a, _) = chain.layout("A", scale, 0.0);
+        let (v, _) = chain.layout("V", scale, 0.0);
+        let kerned_width = font::pixel_bounds(&kerned).unwrap().width();
+        let separate_width =
+            font::pixel_bounds(&a).unwrap().width() + font::pixel_bounds(&v).unwrap().width();
+        assert!(kerned_width < separate_width);
+    }
+
+    #[tokio::test]
+    async fn test_centered() {
+        let fonts = fonts().await;
+        let texts = decoration::parse("AV To\n_small_", true);
+        let textboxes = textboxes(
+            None::<(u32, u32)>,
+            Some(Gravity::Centered),
+            texts,
+            (400, 200),
+            &fonts,
+            &Styles::default(),
+            32,
+            0,
+            &LayoutOptions::default(),
+        )
+        .unwrap();
+
+        let left = textboxes.iter().map(|t| t.rect.0).min().unwrap();
+        let right = textboxes.iter().map(|t| t.rect.0 + t.rect.2).max().unwrap();
+        let top = textboxes.iter().map(|t| t.rect.1).min().unwrap();
+        let bottom = textboxes.iter().map(|t| t.rect.1 + t.rect.3).max().unwrap();
+        assert!((left - (400 - right)).abs() <= 1);
+        assert!((top - (200 - bottom)).abs() <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_position_with_shadow() {
+        let fonts = fonts().await;
+        let texts = decoration::parse("To", true);
+        let textboxes = textboxes(
+            Some((10, 20)),
+            None,
+            texts,
+            (400, 200),
+            &fonts,
+            &Styles::default(),
+            32,
+            2,
+            &LayoutOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(textboxes[0].rect.0, 12);
+        assert_eq!(textboxes[0].rect.1, 22);
+    }
+
+    #[tokio::test]
+    async fn test_vertical_columns() {
+        let fonts = fonts().await;
+        let texts = decoration::parse("AB\nC", true);
+        let textboxes = textboxes(
+            None::<(u32, u32)>,
+            Some(Gravity::Centered),
+            texts,
+            (400, 400),
+            &fonts,
+            &Styles::default(),
+            32,
+            0,
+            &LayoutOptions {
+                writing_mode: WritingMode::Vertical,
+                ..LayoutOptions::default()
+            },
+        )
+        .unwrap();
+
+        let (first, second) = (textboxes[0].rect, textboxes[1].rect);
+        assert!(first.0 > second.0 + second.2);
+        assert!(first.3 > first.2);
+        assert!((first.1 - second.1).abs() <= 2);
+    }
+
+    #[tokio::test]
+    async fn test_vertical_sideways() {
+        let fonts = fonts().await;
+        let chain = fonts.get(Decoration::Normal);
+        let scale = rusttype::Scale::uniform(48.0);
+
+        let (glyphs, _) = chain.layout_vertical("-", scale, 0.0);
+        assert!(glyphs[0].sideways.is_some());
+        let bounds = glyphs[0].pixel_bounding_box().unwrap();
+        assert!(bounds.height() > bounds.width());
+    }
+
+    #[tokio::test]
+    async fn test_arabic_joining() {
+        let fonts = fonts().await;
+        let chain = fonts.get(Decoration::Normal);
+        let scale = rusttype::Scale::uniform(48.0);
+
+        let (isolated, _) = chain.layout("\u{628}", scale, 0.0);
+        let (joined, _) = chain.layout("\u{628}\u{628}", scale, 0.0);
+        assert_eq!(joined.len(), 2);
+        assert!(joined
+            .iter()
+            .all(|g| g.positioned.id() != isolated[0].positioned.id()));
+    }
+
+    #[tokio::test]
+    async fn test_bidi_reorder() {
+        let fonts = fonts().await;
+        let chain = fonts.get(Decoration::Normal);
+        let scale = rusttype::Scale::uniform(48.0);
+
+        let (alef, _) = chain.layout("\u{5d0}", scale, 0.0);
+        let (glyphs, _) = chain.layout("a \u{5d0}\u{5d1}", scale, 0.0);
+        assert_eq!(glyphs.len(), 4);
+        assert_eq!(glyphs[3].positioned.id(), alef[0].positioned.id());
+    }
+
+    fn line_tops(text: &str, options: &LayoutOptions, fonts: &Fonts) -> Vec<i32> {
+        textboxes(
+            Some((0, 0)),
+            None,
+            decoration::parse(text, true),
+            (400, 400),
+            fonts,
+            &Styles::default(),
+            32,
+            0,
+            options,
+        )
+        .unwrap()
+        .iter()
+        .map(|t| t.origin.1)
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn test_line_height() {
+        let fonts = fonts().await;
+        let options = LayoutOptions {
+            line_height: 1.5,
+            ..LayoutOptions::default()
+        };
+        let tops = line_tops("A\nA", &options, &fonts);
+        assert_eq!(tops[1] - tops[0], 48);
+    }
+
+    #[tokio::test]
+    async fn test_paragraph_spacing() {
+        let fonts = fonts().await;
+        let options = LayoutOptions {
+            paragraph_spacing: 0.5,
+            ..LayoutOptions::default()
+        };
+        let tops = line_tops("A\n\nA", &options, &fonts);
+        assert_eq!(tops[1] - tops[0], 32);
+        assert_eq!(tops[2] - tops[1], 16);
+    }
+
+    #[tokio::test]
+    async fn test_letter_spacing() {
+        let fonts = fonts().await;
+        let chain = fonts.get(Decoration::Normal);
+        let scale = rusttype::Scale::uniform(32.0);
+
+        let (_, width) = chain.layout("AVA", scale, 0.0);
+        let (_, tracked) = chain.layout("AVA", scale, 4.0);
+        assert!((tracked - width - 12.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::font::{self, Face, FaceNames, FontSource};
+use std::path::{Path, PathBuf};
+
+const WEIGHTS: [(&str, u16); 16] = [
+    ("thin", 100),
+    ("hairline", 100),
+    ("extralight", 200),
+    ("ultralight", 200),
+    ("light", 300),
+    ("regular", 400),
+    ("normal", 400),
+    ("book", 400),
+    ("medium", 500),
+    ("semibold", 600),
+    ("demibold", 600),
+    ("bold", 700),
+    ("extrabold", 800),
+    ("ultrabold", 800),
+    ("black", 900),
+    ("heavy", 900),
+];
+
+pub const REGULAR_WEIGHT: u16 = 400;
+
+/// Parses a CSS-like font weight such as `700` or `bold`.
+pub fn parse_weight(s: &str) -> Option<u16> {
+    s.parse::<u16>()
+        .ok()
+        .filter(|w| *w >= 1 && *w <= 1000)
+        .or_else(|| weight_from_name(s))
+}
+
+/// Finds the longest weight keyword in a subfamily name, so `SemiBold` is not taken for `Bold`.
+fn weight_from_name(name: &str) -> Option<u16> {
+    let name = name.to_lowercase().replace(&[' ', '-', '_'][..], "");
+    WEIGHTS
+        .iter()
+        .filter(|(keyword, _)| name.contains(keyword))
+        .max_by_key(|(keyword, _)| keyword.len())
+        .map(|(_, weight)| *weight)
+}
+
+fn is_italic(names: &FaceNames) -> bool {
+    names
+        .preferred_subfamily()
+        .map(|s| {
+            let s = s.to_lowercase();
+            s.contains("italic") || s.contains("oblique")
+        })
+        .unwrap_or(false)
+}
+
+/// Returns the directories searched for fonts in order.
+///
+/// `extra_dirs` come first, then the paths in `SANJO_FONT_PATH`,
+/// then the user and system font directories.
+pub fn font_dirs(extra_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs = extra_dirs.to_vec();
+
+    if let Some(paths) = std::env::var_os("SANJO_FONT_PATH") {
+        dirs.extend(std::env::split_paths(&paths));
+    }
+
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    if let Some(data_home) = std::env::var_os("XDG_DATA_HOME") {
+        dirs.push(PathBuf::from(data_home).join("fonts"));
+    } else if let Some(home) = &home {
+        dirs.push(home.join(".local/share/fonts"));
+    }
+    if let Some(home) = &home {
+        dirs.push(home.join(".fonts"));
+    }
+    dirs.push(PathBuf::from("/usr/local/share/fonts"));
+    dirs.push(PathBuf::from("/usr/share/fonts"));
+
+    dirs
+}
+
+fn font_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let mut paths = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            font_files(&path, files);
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| ["ttf", "otf", "ttc", "otc"].contains(&e.to_lowercase().as_str()))
+            .unwrap_or(false)
+        {
+            files.push(path);
+        }
+    }
+}
+
+fn cache_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .map(|p| p.join("sanjo").join("font-families"))
+}
+
+/// Keys a lookup by the searched directories as well, so that a font found in one directory
+/// does not shadow the same family in a directory of a higher priority given later.
+fn cache_key(family: &str, weight: u16, dirs: &str) -> String {
+    format!("{}\t{}\t{}", family.to_lowercase(), weight, dirs)
+}
+
+/// Joins `dirs` into the `DIRS` of a cache line.
+fn cache_dirs(dirs: &[PathBuf]) -> String {
+    std::env::join_paths(dirs)
+        .map(|paths| paths.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| format!("{:?}", dirs))
+}
+
+/// Reads a cache line written as `FAMILY\tWEIGHT\tDIRS\tPATH\tINDEX`.
+fn parse_cache_line(line: &str) -> Option<(String, FontSource)> {
+    let mut split = line.splitn(4, '\t');
+    let key = cache_key(split.next()?, split.next()?.parse().ok()?, split.next()?);
+    let mut split = split.next()?.rsplitn(2, '\t');
+    let index = split.next()?.parse().ok()?;
+    let source = FontSource {
+        path: split.next()?.to_owned(),
+        face: Some(Face::Index(index)),
+    };
+    Some((key, source))
+}
+
+async fn cached(cache_path: &Path, key: &str) -> Option<FontSource> {
+    let cache = tokio::fs::read_to_string(cache_path).await.ok()?;
+    cache
+        .lines()
+        .filter_map(parse_cache_line)
+        .find(|(k, _)| k == key)
+        .map(|(_, source)| source)
+        .filter(|source| Path::new(&source.path).exists())
+}
+
+async fn store(
+    cache_path: &Path,
+    key: &str,
+    path: &str,
+    index: usize,
+) -> Result<(), failure::Error> {
+    if let Some(parent) = cache_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let cache = tokio::fs::read_to_string(cache_path)
+        .await
+        .unwrap_or_default();
+    let mut lines = cache
+        .lines()
+        .filter(|l| parse_cache_line(l).map(|(k, _)| k != key).unwrap_or(false))
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    lines.push(format!("{}\t{}\t{}", key, path, index));
+
+    tokio::fs::write(cache_path, lines.join("\n") + "\n").await?;
+    Ok(())
+}
+
+/// Finds the face of `family` closest to `weight` in `dirs`, preferring upright faces.
+///
+/// Results are cached in `$XDG_CACHE_HOME/sanjo/font-families`.
+pub async fn resolve(
+    family: &str,
+    weight: u16,
+    dirs: &[PathBuf],
+) -> Result<Option<FontSource>, failure::Error> {
+    resolve_cached(family, weight, dirs, cache_path().as_deref()).await
+}
+
+async fn resolve_cached(
+    family: &str,
+    weight: u16,
+    dirs: &[PathBuf],
+    cache_path: Option<&Path>,
+) -> Result<Option<FontSource>, failure::Error> {
+    let key = cache_key(family, weight, &cache_dirs(dirs));
+    if let Some(source) = match cache_path {
+        Some(cache_path) => cached(cache_path, &key).await,
+        None => None,
+    } {
+        log::info!("font family {:?} (cached): {:?}", family, source);
+        return Ok(Some(source));
+    }
+
+    let mut files = Vec::new();
+    for dir in dirs {
+        font_files(dir, &mut files);
+    }
+
+    let mut best: Option<((bool, u16), String, usize)> = None;
+    for file in files {
+        let faces = match font::faces(&file).await {
+            Ok(faces) => faces,
+            Err(error) => {
+                log::debug!("skipping {:?}: {}", file, error);
+                continue;
+            }
+        };
+
+        for (index, names) in faces.iter().enumerate() {
+            let is_family = [names.preferred_family(), names.family.as_deref()]
+                .iter()
+                .filter_map(|n| *n)
+                .any(|n| n.eq_ignore_ascii_case(family));
+            if !is_family {
+                continue;
+            }
+
+            let face_weight = names
+                .preferred_subfamily()
+                .and_then(weight_from_name)
+                .unwrap_or(REGULAR_WEIGHT);
+            let distance = (
+                is_italic(names),
+                (i32::from(face_weight) - i32::from(weight)).unsigned_abs() as u16,
+            );
+            if best.as_ref().map(|(d, _, _)| distance < *d).unwrap_or(true) {
+                best = Some((distance, file.to_string_lossy().into_owned(), index));
+            }
+        }
+    }
+
+    match best {
+        Some((_, path, index)) => {
+            if let Some(cache_path) = cache_path {
+                if let Err(error) = store(cache_path, &key, &path, index).await {
+                    log::warn!("failed to cache the font lookup: {}", error);
+                }
+            }
+            let source = FontSource {
+                path,
+                face: Some(Face::Index(index)),
+            };
+            log::info!("font family {:?}: {:?}", family, source);
+            Ok(Some(source))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_weight_number() {
+        assert_eq!(parse_weight("700"), Some(700));
+        assert_eq!(parse_weight("0"), None);
+        assert_eq!(parse_weight("1200"), None);
+    }
+
+    #[test]
+    fn test_parse_weight_name() {
+        assert_eq!(parse_weight("bold"), Some(700));
+        assert_eq!(parse_weight("SemiBold"), Some(600));
+        assert_eq!(parse_weight("Extra Light"), Some(200));
+        assert_eq!(parse_weight("Regular"), Some(400));
+        assert_eq!(parse_weight("fat"), None);
+    }
+
+    #[test]
+    fn test_parse_cache_line() {
+        let (key, source) =
+            parse_cache_line("noto sans jp\t700\t/fonts\t/fonts/NotoSansCJK.ttc\t3").unwrap();
+        assert_eq!(key, "noto sans jp\t700\t/fonts");
+        assert_eq!(source.path, "/fonts/NotoSansCJK.ttc");
+        assert_eq!(source.face, Some(Face::Index(3)));
+        assert!(parse_cache_line("broken").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_cache_respects_dirs() {
+        let root = std::env::temp_dir().join(format!("sanjo-lookup-{}", std::process::id()));
+        let (high, low) = (root.join("high"), root.join("low"));
+        for dir in &[&high, &low] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::copy("assets/fonts/DejaVuSans.ttf", dir.join("DejaVuSans.ttf")).unwrap();
+        }
+        let cache = root.join("font-families");
+
+        let source = resolve_cached("DejaVu Sans", 400, std::slice::from_ref(&low), Some(&cache))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(source.path.starts_with(low.to_str().unwrap()));
+
+        let source = resolve_cached("DejaVu Sans", 400, &[high.clone(), low], Some(&cache))
+            .await
+            .unwrap()
+            .unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(source.path.starts_with(high.to_str().unwrap()));
+    }
+}
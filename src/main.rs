@@ -1,16 +1,38 @@
+mod adjust;
 mod color;
 mod decoration;
+mod fill;
+mod filter;
+mod font;
+mod frame;
+mod layout;
+mod lookup;
+mod mask;
+mod metadata;
+mod orientation;
+mod overlay;
 mod pair;
+mod shaping;
+mod template;
+mod vertical;
 
 use clap::{arg_enum, value_t};
 use failure::Fail;
 use std::path::Path;
-use tokio::prelude::*;
 
 #[derive(Debug, Clone, Fail)]
 enum ApplicationError {
     #[fail(display = "specified file does not exists: {:?}", _0)]
     InputFileDoesNotExists(Option<String>),
+    #[fail(display = "font family is not found: {}", _0)]
+    FontFamilyNotFound(String),
+    #[fail(
+        display = "the text block with its background padding is larger than {}x{}",
+        _0, _0
+    )]
+    TextLayerTooLarge(u32),
+    #[fail(display = "WebP output is not supported: {}", _0)]
+    WebpOutputNotSupported(String),
 }
 
 async fn check_file_exists<P>(file_path: P) -> Result<(), ApplicationError>
@@ -26,14 +48,45 @@ where
     }
 }
 
-arg_enum! {
-    #[derive(Debug, Clone, Copy)]
-    enum Gravity {
-        UpperCentered,
-        LeftCentered,
-        LowerCentered,
-        RightCentered,
-        Centered,
+/// Rejects a `.webp` output, which would otherwise be written as JPEG or PNG data.
+fn check_output_format(output: &str) -> Result<(), ApplicationError> {
+    let extension = Path::new(output).extension().and_then(|e| e.to_str());
+    match extension {
+        Some(extension) if extension.eq_ignore_ascii_case("webp") => {
+            Err(ApplicationError::WebpOutputNotSupported(output.to_owned()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The image to draw on.
+#[derive(Debug, Clone)]
+enum Input {
+    File(String),
+    /// A blank image of `size` filled with `background`.
+    Canvas {
+        size: pair::Pair<u32>,
+        background: fill::Fill,
+    },
+}
+
+impl Input {
+    fn path(&self) -> Option<&str> {
+        match self {
+            Input::File(path) => Some(path),
+            Input::Canvas { .. } => None,
+        }
+    }
+
+    fn open(&self, auto_orient: bool) -> Result<image::DynamicImage, failure::Error> {
+        match self {
+            Input::File(path) => orientation::open(path, auto_orient),
+            Input::Canvas { size, background } => {
+                let mut canvas = image::RgbaImage::new(size.x, size.y);
+                background.paint(&mut canvas, (0, 0, size.x as i32, size.y as i32));
+                Ok(image::DynamicImage::ImageRgba8(canvas))
+            }
+        }
     }
 }
 
@@ -52,347 +105,526 @@ impl Format {
             Format::Png => image::ImageOutputFormat::PNG,
         }
     }
+
+    fn has_alpha(self) -> bool {
+        match self {
+            Format::Jpeg => false,
+            Format::Png => true,
+        }
+    }
 }
 
-type TextBox = (String, rusttype::Scale, (i32, i32, i32, i32));
+/// Derives a font height from the canvas size when `--font-height` is omitted.
+fn default_font_height(canvas_size: (u32, u32)) -> u32 {
+    (canvas_size.0.min(canvas_size.1) / 10).max(12)
+}
 
-fn textboxes<'a, T, U>(
-    input_position: Option<T>,
-    gravity: Option<Gravity>,
-    texts: U,
-    canvas_size: (u32, u32),
-    font: &rusttype::Font<'_>,
-    height: u32,
-) -> Result<Vec<TextBox>, failure::Error>
-where
-    T: Into<(u32, u32)> + Copy,
-    U: IntoIterator<Item = &'a str>,
-{
-    if let Some(position) = input_position {
-        let position = position.into();
-        let initial_rect = (position.0 as i32, position.1 as i32, 0, 0);
+/// Parses a non-negative multiplier such as `--line-height 1.4`.
+fn parse_factor(s: &str) -> Result<f32, String> {
+    s.parse::<f32>()
+        .ok()
+        .filter(|f| f.is_finite() && *f >= 0.0)
+        .ok_or_else(|| format!("invalid factor: {}", s))
+}
 
-        let (textboxes, _) = texts
-            .into_iter()
-            .map(decoration::DecoratedString::from)
-            .fold((Vec::new(), initial_rect), |(mut vec, previous_rect), l| {
-                let scale_factor = l.decoration.scale_factor();
-                let scale = rusttype::Scale {
-                    x: height as f32 * scale_factor,
-                    y: height as f32 * scale_factor,
-                };
-
-                let v_metrics = font.v_metrics(scale);
-                let textbox_height = v_metrics.ascent.abs() + v_metrics.descent.abs();
-                let textbox_width = font
-                    .layout(&l.body, scale, rusttype::Point { x: 0.0, y: 0.0 })
-                    .map(rusttype::PositionedGlyph::into_unpositioned)
-                    .fold(0.0, |accm, g| accm + g.h_metrics().advance_width)
-                    + 0.5;
-                let difference = if previous_rect.2 != 0 {
-                    (((previous_rect.2 as f32 - textbox_width) / 2.0) + 0.5) as i32
-                } else {
-                    0
-                };
-                let x = previous_rect.0 + difference;
-                let y = previous_rect.1 + previous_rect.3;
-
-                let textbox = (x, y, textbox_width as i32, textbox_height as i32);
-                vec.push((l.body, scale, textbox));
-                (vec, textbox)
-            });
-        Ok(textboxes)
-    } else {
-        let provisional_textboxes =
-            textboxes(Some((0, 0)), None, texts, canvas_size, font, height)?;
-        let max_width = provisional_textboxes
-            .iter()
-            .map(|(_, _, r)| r.2)
-            .max()
-            .unwrap_or(0);
-        let sum_of_heights: i32 = provisional_textboxes.iter().map(|(_, _, r)| r.3).sum();
-        let gravity = gravity.unwrap_or(Gravity::Centered);
-
-        log::info!("canvas size: {:?}", canvas_size);
-        log::info!("max_width: {}", max_width);
-
-        match gravity {
-            Gravity::UpperCentered => {
-                let x = ((canvas_size.0 as f32 - max_width as f32) / 2.0) as i32;
-                let y = ((sum_of_heights as f32 / 16.0) + 0.5) as i32;
-                let textboxes = provisional_textboxes
-                    .into_iter()
-                    .map(|(l, s, r)| (l, s, (r.0 + x, r.1 + y, r.2, r.3)))
-                    .collect::<Vec<_>>();
-                Ok(textboxes)
-            }
-            Gravity::LeftCentered => {
-                let x = ((sum_of_heights as f32 / 16.0) + 0.5) as i32;
-                let y = (((canvas_size.1 as f32 - sum_of_heights as f32) / 2.0) + 0.5) as i32;
-                let textboxes = provisional_textboxes
-                    .into_iter()
-                    .map(|(l, s, r)| (l, s, (r.0 + x, r.1 + y, r.2, r.3)))
-                    .collect::<Vec<_>>();
-                Ok(textboxes)
-            }
-            Gravity::LowerCentered => {
-                let x = (((canvas_size.0 as f32 - max_width as f32) / 2.0) + 0.5) as i32;
-                let y = (canvas_size.1 as i32 - sum_of_heights)
-                    - ((sum_of_heights as f32 / 16.0) + 0.5) as i32;
-                let textboxes = provisional_textboxes
-                    .into_iter()
-                    .map(|(l, s, r)| (l, s, (r.0 + x, r.1 + y, r.2, r.3)))
-                    .collect::<Vec<_>>();
-                Ok(textboxes)
-            }
-            Gravity::RightCentered => {
-                let x = canvas_size.0 as i32 - max_width;
-                let y = (((canvas_size.1 as f32 - sum_of_heights as f32) / 2.0) + 0.5) as i32;
-                let textboxes = provisional_textboxes
-                    .into_iter()
-                    .map(|(l, s, r)| (l, s, (r.0 + x, r.1 + y, r.2, r.3)))
-                    .collect::<Vec<_>>();
-                Ok(textboxes)
-            }
-            Gravity::Centered => {
-                let x = ((canvas_size.0 as f32 - max_width as f32) / 2.0) as i32;
-                let y = ((canvas_size.1 as f32 - sum_of_heights as f32) / 2.0) as i32;
-                let textboxes = provisional_textboxes
-                    .into_iter()
-                    .map(|(l, s, r)| (l, s, (r.0 + x, r.1 + y, r.2, r.3)))
-                    .collect::<Vec<_>>();
-                Ok(textboxes)
-            }
+/// Parses an angle in degrees such as `--text-rotate -30`.
+fn parse_angle(s: &str) -> Result<f32, String> {
+    s.parse::<f32>()
+        .ok()
+        .filter(|d| d.is_finite())
+        .ok_or_else(|| format!("invalid angle: {}", s))
+}
+
+/// The largest width and height of `--canvas` and other generated images,
+/// which keeps a typo from allocating gigabytes.
+const MAX_CANVAS_SIDE: u32 = 10_000;
+
+/// Parses `--canvas` as exactly `WIDTHxHEIGHT` with both sides positive.
+fn parse_canvas_size(s: &str) -> Result<(u32, u32), String> {
+    let sides = s
+        .split('x')
+        .map(|side| side.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>();
+    match sides.as_deref() {
+        Some(&[width, height])
+            if (1..=MAX_CANVAS_SIDE).contains(&width)
+                && (1..=MAX_CANVAS_SIDE).contains(&height) =>
+        {
+            Ok((width, height))
         }
+        _ => Err(format!(
+            "invalid canvas size: {} (expected WIDTHxHEIGHT up to {}x{})",
+            s, MAX_CANVAS_SIDE, MAX_CANVAS_SIDE
+        )),
     }
 }
 
+/// Parses a gamma, which must be positive.
+fn parse_gamma(s: &str) -> Result<f32, String> {
+    parse_factor(s)
+        .ok()
+        .filter(|g| *g > 0.0)
+        .ok_or_else(|| format!("invalid gamma: {}", s))
+}
+
+/// Parses the standard deviation of a Gaussian blur, which must be positive.
+fn parse_sigma(s: &str) -> Result<f32, String> {
+    parse_factor(s)
+        .ok()
+        .filter(|sigma| *sigma > 0.0)
+        .ok_or_else(|| format!("invalid blur sigma: {}", s))
+}
+
+/// Parses the value of `name` with `parse`, or returns `default` if it is absent.
+fn value_or<F>(matches: &clap::ArgMatches<'_>, name: &str, parse: F, default: f32) -> f32
+where
+    F: Fn(&str) -> Result<f32, String>,
+{
+    matches
+        .value_of(name)
+        .and_then(|s| parse(s).ok())
+        .unwrap_or(default)
+}
+
+fn validate_color(s: String) -> Result<(), String> {
+    s.parse::<color::Color>()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn validate_fill(s: String) -> Result<(), String> {
+    s.parse::<fill::Fill>()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Parses an opacity from 0.0 to 1.0.
+fn parse_opacity(s: &str) -> Result<f32, String> {
+    parse_factor(s)
+        .ok()
+        .filter(|o| *o <= 1.0)
+        .ok_or_else(|| format!("invalid opacity: {}", s))
+}
+
+const SHADOW_OFFSET: i32 = 2;
+const SHADOW_OFFSETS: [(i32, i32); 4] = [
+    (SHADOW_OFFSET, SHADOW_OFFSET),
+    (-SHADOW_OFFSET, SHADOW_OFFSET),
+    (-SHADOW_OFFSET, -SHADOW_OFFSET),
+    (SHADOW_OFFSET, -SHADOW_OFFSET),
+];
+
 #[derive(Debug)]
 struct DrawingOptions<P, Q>
 where
     P: AsRef<Path>,
     Q: Into<(u32, u32)> + Copy,
 {
-    in_path: P,
+    input: Input,
     out_path: P,
-    text: String,
-    color: color::Color,
-    shadow_color: Option<color::Color>,
-    font_path: P,
-    height: u32,
+    processing: ProcessingOptions,
+    text: Option<String>,
+    markup: bool,
+    template: bool,
+    color: fill::Fill,
+    shadow_color: Option<fill::Fill>,
+    text_background: Option<fill::Fill>,
+    text_background_padding: Option<u32>,
+    fonts: Vec<font::FontSource>,
+    font_face: Option<font::Face>,
+    font_features: Vec<rustybuzz::Feature>,
+    styles: decoration::Styles,
+    height: Option<u32>,
     position: Option<Q>,
-    gravity: Option<Gravity>,
+    gravity: Option<layout::Gravity>,
+    layout: layout::LayoutOptions,
+    rotation: Option<f32>,
+    overlay: Option<overlay::Overlay>,
+    tiling: Option<overlay::Tiling>,
+}
+
+/// How an image is processed and encoded, whether text is drawn on it or it is resized.
+#[derive(Debug)]
+struct ProcessingOptions {
+    auto_orient: bool,
+    adjustments: adjust::Adjustments,
+    filters: filter::Filters,
+    frame: frame::Frame,
+    mask: mask::Mask,
+    keep_metadata: Vec<metadata::Kind>,
     format: Format,
+    /// Replaces transparency in formats without an alpha channel.
+    flatten_color: color::Color,
 }
 
-async fn draw_text_rgba<P, Q>(options: DrawingOptions<P, Q>) -> Result<(), failure::Error>
+/// Lays out the text and draws each line with `draw`, which is given the bounds of the block
+/// as `(x, y, width, height)` to span fills over it.
+///
+/// To rotate or tile the text, the block is drawn into a `transparent` layer first,
+/// which is rotated clockwise around its center and placed by its bounding box.
+fn draw_text_block<I, P, Q, F>(
+    canvas: &mut image::ImageBuffer<I, Vec<u8>>,
+    text: &str,
+    options: &DrawingOptions<P, Q>,
+    fonts: &font::Fonts,
+    transparent: I,
+    draw: F,
+) -> Result<(), failure::Error>
 where
+    I: image::Pixel<Subpixel = u8> + From<color::Color> + Send + Sync + 'static,
     P: AsRef<Path>,
     Q: Into<(u32, u32)> + Copy,
+    F: Fn(&mut image::ImageBuffer<I, Vec<u8>>, &layout::TextBox, (i32, i32, i32, i32)),
 {
-    let mut font_file = tokio::fs::OpenOptions::new()
-        .read(true)
-        .open(&options.font_path)
-        .await?;
-    let mut font = Vec::new();
-    font_file.read_to_end(&mut font).await?;
-    drop(font_file);
-    let font = rusttype::FontCollection::from_bytes(font)?.font_at(0)?;
-    let image = image::open(&options.in_path)?.to_rgba();
-
-    let texts = options.text.lines();
-    let textboxes = textboxes(
-        options.position,
-        options.gravity,
+    let canvas_size = canvas.dimensions();
+    let height = options
+        .height
+        .unwrap_or_else(|| default_font_height(canvas_size));
+    log::info!("font height: {}", height);
+
+    let texts = decoration::parse(text, options.markup);
+    let shadow_offset = options
+        .shadow_color
+        .as_ref()
+        .map(|_| SHADOW_OFFSET)
+        .unwrap_or(0);
+    let padding = match options.text_background {
+        Some(_) => options.text_background_padding.unwrap_or(height / 4),
+        None => 0,
+    };
+    // The background box covers the shadow as well.
+    let margin = padding.saturating_add(shadow_offset as u32);
+
+    if options.rotation.is_none() && options.tiling.is_none() {
+        let textboxes = layout::textboxes(
+            options.position,
+            options.gravity,
+            texts,
+            canvas_size,
+            fonts,
+            &options.styles,
+            height,
+            shadow_offset,
+            &options.layout,
+        )?;
+        let block = layout::bounds(&textboxes);
+        if let Some(background) = &options.text_background {
+            let (x, y, width, height) = block;
+            let (width, height) = text_layer_size((width, height), margin, canvas_size)?;
+            let margin = margin as i32;
+            background.paint(
+                canvas,
+                (x - margin, y - margin, width as i32, height as i32),
+            );
+        }
+        for textbox in &textboxes {
+            draw(canvas, textbox, block);
+        }
+        return Ok(());
+    }
+
+    let textboxes = layout::textboxes(
+        Some((padding, padding)),
+        None,
         texts,
-        (image.width(), image.height()),
-        &font,
-        options.height,
+        canvas_size,
+        fonts,
+        &options.styles,
+        height,
+        shadow_offset,
+        &options.layout,
     )?;
+    let block = layout::bounds(&textboxes);
+    let (width, height) = text_layer_size((block.2, block.3), margin, canvas_size)?;
 
-    let draw_layer = textboxes
-        .into_iter()
-        .fold(image, |mut accm, (l, scale, textbox)| {
-            if let Some(shadow_color) = options.shadow_color {
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    shadow_color.into(),
-                    textbox.0.max(0) as u32 + 2,
-                    textbox.1.max(0) as u32 + 2,
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    shadow_color.into(),
-                    (textbox.0.max(0) as u32).saturating_sub(2),
-                    textbox.1.max(0) as u32 + 2,
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    shadow_color.into(),
-                    (textbox.0.max(0) as u32).saturating_sub(2),
-                    (textbox.1.max(0) as u32).saturating_sub(2),
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    shadow_color.into(),
-                    textbox.0.max(0) as u32 + 2,
-                    (textbox.1.max(0) as u32).saturating_sub(2),
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    options.color.into(),
-                    textbox.0.max(0) as u32,
-                    textbox.1.max(0) as u32,
-                    scale,
-                    &font,
-                    &l,
-                );
-                accm
-            } else {
-                imageproc::drawing::draw_text(
-                    &mut accm,
-                    options.color.into(),
-                    textbox.0.max(0) as u32,
-                    textbox.1.max(0) as u32,
-                    scale,
-                    &font,
-                    &l,
-                )
-            }
-        });
+    let mut layer = image::ImageBuffer::from_pixel(width, height, transparent);
+    if let Some(background) = &options.text_background {
+        background.paint(&mut layer, (0, 0, width as i32, height as i32));
+    }
+    for textbox in &textboxes {
+        draw(&mut layer, textbox, block);
+    }
+    if let Some(degrees) = options.rotation {
+        layer = overlay::rotate(&layer, degrees, transparent);
+    }
 
-    let mut file = std::fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(options.out_path)?;
-    image::DynamicImage::ImageRgba8(draw_layer)
-        .write_to(&mut file, options.format.to_image_output_format())?;
+    let size = (layer.width() as i32, layer.height() as i32);
+    let origin = layout::place(options.position, options.gravity, canvas_size, size);
+    overlay::stamp(canvas, &layer, origin, options.tiling.as_ref());
 
     Ok(())
 }
 
-async fn draw_text_luma_alpha<P, Q>(options: DrawingOptions<P, Q>) -> Result<(), failure::Error>
+/// Returns the size of a text block of `size` with `margin` on every side.
+///
+/// Each side may be as long as `MAX_CANVAS_SIDE` or the diagonal of `canvas_size`, whichever is longer.
+fn text_layer_size(
+    size: (i32, i32),
+    margin: u32,
+    canvas_size: (u32, u32),
+) -> Result<(u32, u32), ApplicationError> {
+    let diagonal = f64::from(canvas_size.0)
+        .hypot(f64::from(canvas_size.1))
+        .ceil() as u32;
+    let limit = diagonal.max(MAX_CANVAS_SIDE);
+    let extend = |side: i32| {
+        margin
+            .checked_mul(2)
+            .and_then(|margins| margins.checked_add(side.max(0) as u32))
+            .filter(|side| *side <= limit)
+    };
+    match (extend(size.0), extend(size.1)) {
+        (Some(width), Some(height)) => Ok((width, height)),
+        _ => Err(ApplicationError::TextLayerTooLarge(limit)),
+    }
+}
+
+/// Returns the text to draw with its placeholders expanded for the input image of `size`
+/// if `--template` is given.
+fn expand_text<P, Q>(
+    options: &DrawingOptions<P, Q>,
+    size: (u32, u32),
+) -> Result<Option<String>, failure::Error>
 where
     P: AsRef<Path>,
     Q: Into<(u32, u32)> + Copy,
 {
-    let mut font_file = tokio::fs::OpenOptions::new()
-        .read(true)
-        .open(&options.font_path)
-        .await?;
-    let mut font = Vec::new();
-    font_file.read_to_end(&mut font).await?;
-    drop(font_file);
-    let font = rusttype::FontCollection::from_bytes(font)?.font_at(0)?;
-    let image = image::open(&options.in_path)?.to_luma_alpha();
-
-    let texts = options.text.lines();
-    let textboxes = textboxes(
-        options.position,
-        options.gravity,
-        texts,
-        (image.width(), image.height()),
-        &font,
-        options.height,
+    let text = match &options.text {
+        Some(text) if options.template => text,
+        text => return Ok(text.clone()),
+    };
+    let variables = template::Variables::new(options.input.path(), size);
+    let text = template::expand(text, &variables)?;
+    log::info!("expanded text: {}", text);
+    Ok(Some(text))
+}
+
+async fn load_fonts<P, Q>(options: &DrawingOptions<P, Q>) -> Result<font::Fonts, failure::Error>
+where
+    P: AsRef<Path>,
+    Q: Into<(u32, u32)> + Copy,
+{
+    font::Fonts::load(
+        &options.fonts,
+        options.font_face.as_ref(),
+        &options.styles,
+        &options.font_features,
+    )
+    .await
+}
+
+/// Draws the shadow and the glyphs of `textbox` with the fills of `options` spanning `block`.
+fn draw_textbox<I, P, Q>(
+    canvas: &mut image::ImageBuffer<I, Vec<u8>>,
+    textbox: &layout::TextBox,
+    block: (i32, i32, i32, i32),
+    options: &DrawingOptions<P, Q>,
+) where
+    I: image::Pixel<Subpixel = u8> + From<color::Color> + 'static,
+    P: AsRef<Path>,
+    Q: Into<(u32, u32)> + Copy,
+{
+    let (x, y) = textbox.origin;
+    let style_fill;
+    let fill = match options.styles.get(textbox.decoration).color {
+        Some(color) => {
+            style_fill = fill::Fill::Solid(color);
+            &style_fill
+        }
+        None => &options.color,
+    };
+    if let Some(shadow_color) = &options.shadow_color {
+        for (dx, dy) in &SHADOW_OFFSETS {
+            font::fill_glyphs_mut(
+                canvas,
+                |px, py| shadow_color.at((px - dx, py - dy), block).into(),
+                x + dx,
+                y + dy,
+                &textbox.glyphs,
+            );
+        }
+    }
+    font::fill_glyphs_mut(
+        canvas,
+        |px, py| fill.at((px, py), block).into(),
+        x,
+        y,
+        &textbox.glyphs,
+    );
+}
+
+async fn draw_rgba<P, Q>(options: DrawingOptions<P, Q>) -> Result<(), failure::Error>
+where
+    P: AsRef<Path>,
+    Q: Into<(u32, u32)> + Copy,
+{
+    let processing = &options.processing;
+    let mut image = options.input.open(processing.auto_orient)?.to_rgba();
+    let text = expand_text(&options, image.dimensions())?;
+    processing.adjustments.apply(&mut image);
+    processing.filters.apply(&mut image);
+    let mut image = processing.frame.apply(image)?;
+
+    if let Some(overlay) = &options.overlay {
+        let layer = overlay.load(image.dimensions())?.to_rgba();
+        let origin = overlay.origin(image.dimensions(), layer.dimensions());
+        overlay::stamp(&mut image, &layer, origin, options.tiling.as_ref());
+    }
+
+    let text = match &text {
+        Some(text) => text,
+        None => return write_image(image::DynamicImage::ImageRgba8(image), &options),
+    };
+    let fonts = load_fonts(&options).await?;
+    draw_text_block(
+        &mut image,
+        text,
+        &options,
+        &fonts,
+        image::Rgba([0, 0, 0, 0]),
+        |canvas, textbox, block| draw_textbox(canvas, textbox, block, &options),
     )?;
 
-    let draw_layer = textboxes
-        .into_iter()
-        .fold(image, |mut accm, (l, scale, textbox)| {
-            if options.shadow_color.is_some() {
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    image::LumaA([255, 255]),
-                    textbox.0.max(0) as u32 + 2,
-                    textbox.1.max(0) as u32 + 2,
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    image::LumaA([255, 255]),
-                    (textbox.0.max(0) as u32).saturating_sub(2),
-                    textbox.1.max(0) as u32 + 2,
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    image::LumaA([255, 255]),
-                    (textbox.0.max(0) as u32).saturating_sub(2),
-                    (textbox.1.max(0) as u32).saturating_sub(2),
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    image::LumaA([255, 255]),
-                    textbox.0.max(0) as u32 + 2,
-                    (textbox.1.max(0) as u32).saturating_sub(2),
-                    scale,
-                    &font,
-                    &l,
-                );
-                imageproc::drawing::draw_text_mut(
-                    &mut accm,
-                    image::LumaA([0, 255]),
-                    textbox.0.max(0) as u32,
-                    textbox.1.max(0) as u32,
-                    scale,
-                    &font,
-                    &l,
-                );
-                accm
-            } else {
-                imageproc::drawing::draw_text(
-                    &mut accm,
-                    image::LumaA([0, 255]),
-                    textbox.0.max(0) as u32,
-                    textbox.1.max(0) as u32,
-                    scale,
-                    &font,
-                    &l,
-                )
-            }
-        });
+    write_image(image::DynamicImage::ImageRgba8(image), &options)
+}
 
-    let mut file = std::fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(options.out_path)?;
-    image::DynamicImage::ImageLumaA8(draw_layer)
-        .write_to(&mut file, options.format.to_image_output_format())?;
+async fn draw_luma_alpha<P, Q>(options: DrawingOptions<P, Q>) -> Result<(), failure::Error>
+where
+    P: AsRef<Path>,
+    Q: Into<(u32, u32)> + Copy,
+{
+    let processing = &options.processing;
+    let mut image = options.input.open(processing.auto_orient)?.to_luma_alpha();
+    let text = expand_text(&options, image.dimensions())?;
+    processing.adjustments.apply(&mut image);
+    processing.filters.apply(&mut image);
+    let mut image = processing.frame.apply(image)?;
+
+    if let Some(overlay) = &options.overlay {
+        let layer = overlay.load(image.dimensions())?.to_luma_alpha();
+        let origin = overlay.origin(image.dimensions(), layer.dimensions());
+        overlay::stamp(&mut image, &layer, origin, options.tiling.as_ref());
+    }
+
+    let text = match &text {
+        Some(text) => text,
+        None => return write_image(image::DynamicImage::ImageLumaA8(image), &options),
+    };
+    let fonts = load_fonts(&options).await?;
+    draw_text_block(
+        &mut image,
+        text,
+        &options,
+        &fonts,
+        image::LumaA([0, 0]),
+        |canvas, textbox, block| draw_textbox(canvas, textbox, block, &options),
+    )?;
+
+    write_image(image::DynamicImage::ImageLumaA8(image), &options)
+}
+
+fn write_image<P, Q>(
+    image: image::DynamicImage,
+    options: &DrawingOptions<P, Q>,
+) -> Result<(), failure::Error>
+where
+    P: AsRef<Path>,
+    Q: Into<(u32, u32)> + Copy,
+{
+    let image = options.processing.mask.apply_dynamic(image)?;
+    save_image(
+        &image,
+        options.input.path().map(Path::new),
+        options.out_path.as_ref(),
+        &options.processing,
+    )
+}
+
+/// Encodes `image` to `out_path` with the kinds of metadata of the input image at `in_path`
+/// that `options` keeps.
+///
+/// The image is flattened onto the flatten color if the format has no alpha channel.
+fn save_image(
+    image: &image::DynamicImage,
+    in_path: Option<&Path>,
+    out_path: &Path,
+    options: &ProcessingOptions,
+) -> Result<(), failure::Error> {
+    let format = options.format;
+    let flattened = if format.has_alpha() {
+        None
+    } else {
+        flatten(image, options.flatten_color)
+    };
+    if let Some((_, transparent)) = flattened.as_ref().filter(|(_, t)| *t > 0) {
+        log::warn!(
+            "{:?} has no alpha channel. {} pixels that are not opaque are flattened onto {}.",
+            format,
+            transparent,
+            options.flatten_color
+        );
+    }
+    let image = flattened.as_ref().map_or(image, |(image, _)| image);
+
+    let mut data = Vec::new();
+    image.write_to(&mut data, format.to_image_output_format())?;
+    if let Some(in_path) = in_path {
+        data = metadata::Metadata::load(in_path, &options.keep_metadata, options.auto_orient)
+            .embed(data);
+    }
+
+    std::fs::write(out_path, data)?;
 
     Ok(())
 }
 
+/// Blends `image` over `color`, which is made opaque over white, if it has an alpha channel.
+///
+/// Returns the flattened image and the number of pixels that were not opaque.
+fn flatten(
+    image: &image::DynamicImage,
+    color: color::Color,
+) -> Option<(image::DynamicImage, usize)> {
+    use image::{DynamicImage::*, GenericImageView};
+
+    let (width, height) = image.dimensions();
+    let backdrop = color.over(color::Color::white());
+    let transparent = std::cell::Cell::new(0);
+    let blend = |[r, g, b, a]: [u8; 4]| {
+        if a < 255 {
+            transparent.set(transparent.get() + 1);
+        }
+        color::Color::new(r, g, b, a).over(backdrop)
+    };
+
+    let flattened = match image {
+        ImageLumaA8(buffer) => ImageLuma8(image::ImageBuffer::from_fn(width, height, |x, y| {
+            let image::LumaA([l, a]) = *buffer.get_pixel(x, y);
+            let image::LumaA([l, _]) = blend([l, l, l, a]).into();
+            image::Luma([l])
+        })),
+        ImageRgba8(_) | ImageBgra8(_) => {
+            let rgba = image.to_rgba();
+            ImageRgb8(image::ImageBuffer::from_fn(width, height, |x, y| {
+                let image::Rgba([r, g, b, _]) = blend(rgba.get_pixel(x, y).0).into();
+                image::Rgb([r, g, b])
+            }))
+        }
+        _ => return None,
+    };
+    Some((flattened, transparent.get()))
+}
+
 async fn resize_image_keep_aspect_ratio<P>(
     in_path: P,
     out_path: P,
     width: u32,
-    format: Format,
+    options: &ProcessingOptions,
 ) -> Result<(), failure::Error>
 where
     P: AsRef<Path>,
 {
-    let image = image::open(in_path)?;
+    let image = orientation::open(&in_path, options.auto_orient)?;
 
     let new_image = image.resize(
         width,
@@ -400,28 +632,32 @@ where
         image::imageops::FilterType::CatmullRom,
     );
     drop(image);
+    let new_image = options
+        .filters
+        .apply_dynamic(options.adjustments.apply_dynamic(new_image));
+    let new_image = options
+        .mask
+        .apply_dynamic(options.frame.apply_dynamic(new_image)?)?;
 
-    let mut file = std::fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(out_path)?;
-    new_image.write_to(&mut file, format.to_image_output_format())?;
-
-    Ok(())
+    save_image(
+        &new_image,
+        Some(in_path.as_ref()),
+        out_path.as_ref(),
+        options,
+    )
 }
 
 async fn resize_image<P, Q>(
     in_path: P,
     out_path: P,
     dimensions: Q,
-    format: Format,
+    options: &ProcessingOptions,
 ) -> Result<(), failure::Error>
 where
     P: AsRef<Path>,
     Q: Into<(u32, u32)>,
 {
-    let image = image::open(in_path)?;
+    let image = orientation::open(&in_path, options.auto_orient)?;
     let target_dimensions = dimensions.into();
 
     let new_image = image.resize_exact(
@@ -430,30 +666,288 @@ where
         image::imageops::FilterType::CatmullRom,
     );
     drop(image);
+    let new_image = options
+        .filters
+        .apply_dynamic(options.adjustments.apply_dynamic(new_image));
+    let new_image = options
+        .mask
+        .apply_dynamic(options.frame.apply_dynamic(new_image)?)?;
 
-    let mut file = std::fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(out_path)?;
-    new_image.write_to(&mut file, format.to_image_output_format())?;
-
-    Ok(())
+    save_image(
+        &new_image,
+        Some(in_path.as_ref()),
+        out_path.as_ref(),
+        options,
+    )
 }
 
 fn add_options_to_app<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    let text = clap::Arg::with_name("text")
+        .short("t")
+        .long("text")
+        .takes_value(true)
+        .value_name("STRING")
+        .help(
+            "Sets the text to draw. `\\n` starts a new line. \
+             Lines can be decorated with `# `, `## `, `### `, `*...*` and `_..._`. \
+             Use `\\*`, `\\_`, `\\#` and `\\\\` to draw them literally.",
+        );
+    let text_file = clap::Arg::with_name("text_file")
+        .long("text-file")
+        .takes_value(true)
+        .value_name("PATH")
+        .help("Reads the text to draw from PATH, or from the standard input if PATH is `-`.");
+    let (text, text_file) = if font::HAS_EMBEDDED_FONT {
+        (text, text_file)
+    } else {
+        (
+            text.requires("font_source"),
+            text_file.requires("font_source"),
+        )
+    };
+
     app.version(clap::crate_version!())
         .author(clap::crate_authors!())
         .name(clap::crate_name!())
         .setting(clap::AppSettings::ArgRequiredElseHelp)
         .arg(
-            clap::Arg::with_name("text")
-                .short("t")
-                .long("text")
+            clap::Arg::with_name("canvas")
+                .long("canvas")
+                .takes_value(true)
+                .value_name("WIDTHxHEIGHT")
+                .help("Draws on a blank image of WIDTHxHEIGHT, up to 10000x10000, instead of an input image.")
+                .validator(|s| parse_canvas_size(&s).map(|_| ())),
+        )
+        .group(
+            clap::ArgGroup::with_name("source")
+                .args(&["input", "canvas"])
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name("background")
+                .long("background")
+                .takes_value(true)
+                .value_name("COLOR")
+                .help(
+                    "Fills the canvas with a color, a gradient or a pattern such as \
+                     `checker(16, white, #ccc)` or `stripes(45deg, 8, black, gold)`. Transparent is default.",
+                )
+                .validator(validate_fill)
+                .requires("canvas"),
+        )
+        .arg(
+            clap::Arg::with_name("flatten_color")
+                .long("flatten-color")
+                .takes_value(true)
+                .value_name("COLOR")
+                .help(
+                    "Sets the color that transparency is flattened onto in formats without alpha such as JPEG. \
+                     `white` is default.",
+                )
+                .validator(validate_color),
+        )
+        .arg(text)
+        .arg(text_file)
+        .group(clap::ArgGroup::with_name("text_source").args(&["text", "text_file"]))
+        .arg(
+            clap::Arg::with_name("color")
+                .short("c")
+                .long("color")
+                .takes_value(true)
+                .value_name("COLOR")
+                .help(
+                    "Sets the text color to draw as `#RGB[A]`, `#RRGGBB[AA]`, a CSS color name, `rgb()` or `hsl()`, \
+                     or a gradient such as `linear(90deg, #ff6a00, #ee0979)` or `radial(white, black 80%)`.",
+                )
+                .validator(validate_fill),
+        )
+        .arg(
+            clap::Arg::with_name("shadow_color")
+                .short("s")
+                .long("shadow-color")
+                .takes_value(true)
+                .value_name("COLOR")
+                .help("Sets the text shadow color to draw, in the same formats as `--color`.")
+                .validator(validate_fill),
+        )
+        .arg(
+            clap::Arg::with_name("text_background")
+                .long("text-background")
+                .takes_value(true)
+                .value_name("COLOR")
+                .help("Fills a box behind the text, in the same formats as `--color`.")
+                .validator(validate_fill)
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("text_background_padding")
+                .long("text-background-padding")
                 .takes_value(true)
-                .value_name("STRING")
-                .help("Sets the text to draw.")
-                .requires_all(&["font", "font_height"]),
+                .value_name("PIXELS")
+                .help("Sets the space between the text and the edges of its background box. A quarter of the font height is default.")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .requires("text_background"),
+        )
+        .arg(
+            clap::Arg::with_name("no_markup")
+                .long("no-markup")
+                .help("Draws every line of the text as-is without interpreting decorations.")
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("template")
+                .long("template")
+                .help(
+                    "Replaces `{filename}`, `{width}`, `{height}`, `{date:%Y-%m-%d}` and `{exif.TAG}` \
+                     in the text with the values of the input image; use `{{` and `}}` for braces.",
+                )
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("vertical")
+                .long("vertical")
+                .help("Draws the text vertically in columns from right to left.")
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("text_rotate")
+                .long("text-rotate")
+                .takes_value(true)
+                .value_name("DEGREES")
+                .help("Rotates the text clockwise around its center by DEGREES.")
+                .validator(|s| parse_angle(&s).map(|_| ()))
+                .allow_hyphen_values(true)
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("line_height")
+                .long("line-height")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .help("Sets the distance between lines as a multiple of the font height. 1.0 is default.")
+                .validator(|s| parse_factor(&s).map(|_| ()))
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("letter_spacing")
+                .long("letter-spacing")
+                .takes_value(true)
+                .value_name("TRACKING")
+                .help("Adds space after each character in thousandths of the font height.")
+                .validator(|s| {
+                    s.parse::<f32>()
+                        .map(|_| ())
+                        .map_err(|_| format!("invalid letter spacing: {}", s))
+                })
+                .allow_hyphen_values(true)
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("paragraph_spacing")
+                .long("paragraph-spacing")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .help("Sets the distance a blank line takes as a multiple of the line height. 1.0 is default.")
+                .validator(|s| parse_factor(&s).map(|_| ()))
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("style")
+                .short("y")
+                .long("style")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("LEVEL:KEY=VALUE,...")
+                .help(
+                    "Sets the scale, font and color of a decoration level. \
+                     e.g. `h1:scale=2.4,font=Bold.ttf,color=#ff0000ff`. \
+                     LEVEL is one of `h1`, `h2`, `h3`, `larger`, `normal` and `smaller`.",
+                )
+                .requires("text_source"),
+        )
+        .arg(
+            clap::Arg::with_name("font_family")
+                .long("font-family")
+                .takes_value(true)
+                .value_name("FAMILY")
+                .help(
+                    "Sets the font family to draw a text. \
+                     It is searched in `--font-dir`, `SANJO_FONT_PATH` and the standard font directories, \
+                     and `--font` files are used as fallbacks.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("font_weight")
+                .long("font-weight")
+                .takes_value(true)
+                .value_name("WEIGHT")
+                .help("Sets the font weight such as `bold` or `700`. `regular` is default.")
+                .validator(|s| {
+                    lookup::parse_weight(&s)
+                        .map(|_| ())
+                        .ok_or_else(|| format!("invalid font weight: {}", s))
+                })
+                .requires("font_family"),
+        )
+        .arg(
+            clap::Arg::with_name("font_dir")
+                .long("font-dir")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("DIR")
+                .help("Adds a directory to search for `--font-family`.")
+                .requires("font_family"),
+        )
+        .group(
+            clap::ArgGroup::with_name("font_source")
+                .args(&["font", "font_family"])
+                .multiple(true),
+        )
+        .arg(
+            clap::Arg::with_name("font_index")
+                .long("font-index")
+                .takes_value(true)
+                .value_name("INDEX")
+                .help(
+                    "Selects the face at INDEX in font collections (.ttc/.otc). \
+                     `PATH#INDEX` or `PATH#NAME` in `--font` overrides it per file.",
+                )
+                .validator(|s| {
+                    s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|_| format!("invalid face index: {}", s))
+                })
+                .conflicts_with("font_name")
+                .requires("font_source"),
+        )
+        .arg(
+            clap::Arg::with_name("font_name")
+                .long("font-name")
+                .takes_value(true)
+                .value_name("NAME")
+                .help(
+                    "Selects the face named NAME (PostScript, full or family name) \
+                     in font collections (.ttc/.otc).",
+                )
+                .requires("font_source"),
+        )
+        .arg(
+            clap::Arg::with_name("font_features")
+                .long("font-features")
+                .takes_value(true)
+                .value_name("FEATURES")
+                .help(
+                    "Sets OpenType features to apply, separated by commas, such as `liga,ss01`. \
+                     Prefix a feature with `-` to disable it.",
+                )
+                .validator(|s| {
+                    shaping::parse_features(&s)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .requires("text_source"),
         )
         .arg(
             clap::Arg::with_name("gravity")
@@ -473,6 +967,294 @@ fn add_options_to_app<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
                     "Centered",
                 ]),
         )
+        .arg(
+            clap::Arg::with_name("overlay")
+                .long("overlay")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Stamps the image at PATH, such as a logo, onto the input image under the text."),
+        )
+        .arg(
+            clap::Arg::with_name("overlay_gravity")
+                .long("overlay-gravity")
+                .takes_value(true)
+                .value_name("GRAVITY")
+                .help("Sets the overlay position by default values. `Centered` is default.")
+                .possible_values(&layout::Gravity::variants())
+                .requires("overlay"),
+        )
+        .arg(
+            clap::Arg::with_name("overlay_position")
+                .long("overlay-position")
+                .takes_value(true)
+                .value_name("X_POSITIONxY_POSITION")
+                .help("Sets the overlay position. Conflicts with `--overlay-gravity` option.")
+                .conflicts_with("overlay_gravity")
+                .requires("overlay"),
+        )
+        .arg(
+            clap::Arg::with_name("overlay_scale")
+                .long("overlay-scale")
+                .takes_value(true)
+                .value_name("PERCENT")
+                .help("Scales the overlay to PERCENT of the input image width, keeping its aspect ratio.")
+                .validator(|s| parse_factor(&s).map(|_| ()))
+                .requires("overlay"),
+        )
+        .arg(
+            clap::Arg::with_name("overlay_opacity")
+                .long("overlay-opacity")
+                .takes_value(true)
+                .value_name("OPACITY")
+                .help("Sets the overlay opacity from 0.0 to 1.0. 1.0 is default.")
+                .validator(|s| parse_opacity(&s).map(|_| ()))
+                .requires("overlay"),
+        )
+        .arg(
+            clap::Arg::with_name("overlay_margin")
+                .long("overlay-margin")
+                .takes_value(true)
+                .value_name("PIXELS")
+                .help("Keeps PIXELS between the overlay and the image edges when placed by gravity.")
+                .validator(|s| {
+                    s.parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|_| format!("invalid margin: {}", s))
+                })
+                .requires("overlay"),
+        )
+        .arg(
+            clap::Arg::with_name("overlay_rotate")
+                .long("overlay-rotate")
+                .takes_value(true)
+                .value_name("DEGREES")
+                .help("Rotates the overlay clockwise around its center by DEGREES.")
+                .validator(|s| parse_angle(&s).map(|_| ()))
+                .allow_hyphen_values(true)
+                .requires("overlay"),
+        )
+        .arg(
+            clap::Arg::with_name("tile")
+                .long("tile")
+                .help("Repeats the text and the overlay across the whole image.")
+                .conflicts_with_all(&["position", "gravity", "overlay_position", "overlay_gravity"]),
+        )
+        .arg(
+            clap::Arg::with_name("tile_spacing")
+                .long("tile-spacing")
+                .takes_value(true)
+                .value_name("XxY")
+                .help("Sets the gaps between tiles. The tile height is default.")
+                .requires("tile"),
+        )
+        .arg(
+            clap::Arg::with_name("tile_stagger")
+                .long("tile-stagger")
+                .help("Shifts every other row of tiles by half a tile.")
+                .requires("tile"),
+        )
+        .arg(
+            clap::Arg::with_name("tile_opacity")
+                .long("tile-opacity")
+                .takes_value(true)
+                .value_name("OPACITY")
+                .help("Sets the tile opacity from 0.0 to 1.0. 1.0 is default.")
+                .validator(|s| parse_opacity(&s).map(|_| ()))
+                .requires("tile"),
+        )
+        .arg(
+            clap::Arg::with_name("brightness")
+                .long("brightness")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .help("Multiplies the brightness of the input image. 1.0 is default.")
+                .validator(|s| parse_factor(&s).map(|_| ())),
+        )
+        .arg(
+            clap::Arg::with_name("contrast")
+                .long("contrast")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .help("Multiplies the contrast of the input image around the middle gray. 1.0 is default.")
+                .validator(|s| parse_factor(&s).map(|_| ())),
+        )
+        .arg(
+            clap::Arg::with_name("gamma")
+                .long("gamma")
+                .takes_value(true)
+                .value_name("GAMMA")
+                .help("Applies gamma correction to the input image. Values above 1.0 brighten the midtones.")
+                .validator(|s| parse_gamma(&s).map(|_| ())),
+        )
+        .arg(
+            clap::Arg::with_name("saturation")
+                .long("saturation")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .help("Multiplies the saturation of the input image. 0.0 makes it grayscale.")
+                .validator(|s| parse_factor(&s).map(|_| ())),
+        )
+        .arg(
+            clap::Arg::with_name("hue_rotate")
+                .long("hue-rotate")
+                .takes_value(true)
+                .value_name("DEGREES")
+                .help("Rotates the hue of the input image by DEGREES.")
+                .validator(|s| parse_angle(&s).map(|_| ()))
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            clap::Arg::with_name("blur")
+                .long("blur")
+                .takes_value(true)
+                .value_name("SIGMA")
+                .help("Applies a Gaussian blur with the standard deviation SIGMA in pixels.")
+                .validator(|s| parse_sigma(&s).map(|_| ())),
+        )
+        .arg(
+            clap::Arg::with_name("box_blur")
+                .long("box-blur")
+                .takes_value(true)
+                .value_name("RADIUS")
+                .help("Averages each pixel with its neighbors within RADIUS pixels.")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            clap::Arg::with_name("sharpen")
+                .long("sharpen")
+                .takes_value(true)
+                .value_name("AMOUNT")
+                .help("Sharpens the image by AMOUNT. 0.5 is a light sharpen after downscaling.")
+                .validator(|s| parse_factor(&s).map(|_| ())),
+        )
+        .arg(
+            clap::Arg::with_name("unsharp")
+                .long("unsharp")
+                .takes_value(true)
+                .value_name("RADIUS,AMOUNT[,THRESHOLD]")
+                .help("Applies an unsharp mask, skipping differences below THRESHOLD (0 to 255).")
+                .validator(|s| filter::Filter::parse_unsharp_mask(&s).map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            clap::Arg::with_name("filter_region")
+                .long("filter-region")
+                .takes_value(true)
+                .value_name("WIDTHxHEIGHT+X+Y")
+                .help("Restricts the blur and sharpen filters to a region of the image.")
+                .validator(|s| filter::Region::parse(&s).map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            clap::Arg::with_name("padding")
+                .long("padding")
+                .takes_value(true)
+                .value_name("PIXELS")
+                .help("Adds padding around the image: ALL, VERTICAL,HORIZONTAL or TOP,RIGHT,BOTTOM,LEFT as in CSS.")
+                .validator(|s| frame::Sides::parse(&s).map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            clap::Arg::with_name("padding_color")
+                .long("padding-color")
+                .takes_value(true)
+                .value_name("COLOR")
+                .help("Sets the color of the padding and the aspect ratio extension. `white` is default; `transparent` is allowed.")
+                .validator(validate_color),
+        )
+        .arg(
+            clap::Arg::with_name("aspect_ratio")
+                .long("aspect-ratio")
+                .takes_value(true)
+                .value_name("WIDTH:HEIGHT")
+                .help("Extends the canvas to an aspect ratio such as `1:1`, keeping the whole image.")
+                .validator(|s| frame::parse_aspect_ratio(&s).map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            clap::Arg::with_name("aspect_gravity")
+                .long("aspect-gravity")
+                .takes_value(true)
+                .value_name("GRAVITY")
+                .help("Sets where the image is placed in the extended canvas. `Centered` is default.")
+                .possible_values(&layout::Gravity::variants())
+                .requires("aspect_ratio"),
+        )
+        .arg(
+            clap::Arg::with_name("border")
+                .long("border")
+                .takes_value(true)
+                .value_name("PIXELS")
+                .help("Adds a border outside the padding, with the same syntax as `--padding`.")
+                .validator(|s| frame::Sides::parse(&s).map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            clap::Arg::with_name("border_color")
+                .long("border-color")
+                .takes_value(true)
+                .value_name("COLOR")
+                .help("Sets the border color. `black` is default.")
+                .validator(validate_color)
+                .requires("border"),
+        )
+        .arg(
+            clap::Arg::with_name("round_corners")
+                .long("round-corners")
+                .takes_value(true)
+                .value_name("RADIUS")
+                .help("Makes the corners of the output transparent, rounded by RADIUS pixels.")
+                .validator(|s| {
+                    s.parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|_| format!("invalid radius: {}", s))
+                }),
+        )
+        .arg(
+            clap::Arg::with_name("mask_shape")
+                .long("mask-shape")
+                .takes_value(true)
+                .value_name("SHAPE")
+                .help("Makes the output transparent outside a circle or an ellipse inscribed in it.")
+                .possible_values(&mask::Shape::variants())
+        )
+        .arg(
+            clap::Arg::with_name("mask_image")
+                .long("mask-image")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Masks the output by an image scaled to its size."),
+        )
+        .arg(
+            clap::Arg::with_name("mask_channel")
+                .long("mask-channel")
+                .takes_value(true)
+                .value_name("CHANNEL")
+                .help("Sets the channel of the mask image used as opacity. `Alpha` is default.")
+                .possible_values(&mask::Channel::variants())
+                .requires("mask_image"),
+        )
+        .arg(
+            clap::Arg::with_name("no_auto_orient")
+                .long("no-auto-orient")
+                .help("Keeps the stored pixel orientation instead of applying the EXIF Orientation tag."),
+        )
+        .arg(
+            clap::Arg::with_name("keep_metadata")
+                .long("keep-metadata")
+                .help("Copies all EXIF, ICC and XMP metadata of the input image to the output. Only the ICC profile is kept by default. \
+                       JPEG, PNG and WebP inputs are read; outputs are JPEG or PNG as `.webp` outputs are rejected."),
+        )
+        .arg(
+            clap::Arg::with_name("strip_metadata")
+                .long("strip-metadata")
+                .help("Writes the output without any metadata.")
+                .conflicts_with("keep_metadata"),
+        )
+        .arg(
+            clap::Arg::with_name("keep")
+                .long("keep")
+                .takes_value(true)
+                .value_name("exif,icc,xmp")
+                .help("Copies only the listed kinds of metadata to the output.")
+                .validator(|s| metadata::parse_kinds(&s).map(|_| ()).map_err(|e| e.to_string()))
+                .conflicts_with_all(&["keep_metadata", "strip_metadata"]),
+        )
         .arg(
             clap::Arg::with_name("format")
                 .short("m")
@@ -482,71 +1264,388 @@ fn add_options_to_app<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
                 .help("Sets the output file format. `Png` is default.")
                 .possible_values(&["Png", "Jpeg"]),
         )
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            clap::SubCommand::with_name("fonts")
+                .about("Lists the faces contained in font files.")
+                .arg(
+                    clap::Arg::with_name("files")
+                        .value_name("PATH_TO_FONT")
+                        .help("Sets the font files to inspect.")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+}
+
+async fn list_fonts(matches: &clap::ArgMatches<'_>) -> Result<(), failure::Error> {
+    for path in matches.values_of("files").into_iter().flatten() {
+        println!("{}", path);
+        for (index, face) in font::faces(path).await?.into_iter().enumerate() {
+            println!(
+                "  #{}: {} ({} {})",
+                index,
+                face.postscript_name.as_deref().unwrap_or("-"),
+                face.family.as_deref().unwrap_or("-"),
+                face.subfamily.as_deref().unwrap_or("-"),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reads the text to draw from `--text` or `--text-file`.
+async fn read_text(matches: &clap::ArgMatches<'_>) -> Result<Option<String>, failure::Error> {
+    use tokio::io::AsyncReadExt;
+
+    if let Some(text) = matches.value_of("text") {
+        return Ok(Some(text.to_owned()));
+    }
+    match matches.value_of("text_file") {
+        Some("-") => {
+            let mut text = String::new();
+            tokio::io::stdin().read_to_string(&mut text).await?;
+            Ok(Some(text))
+        }
+        Some(path) => Ok(Some(tokio::fs::read_to_string(path).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the padding, the aspect ratio extension and the border.
+fn read_frame(matches: &clap::ArgMatches<'_>) -> Result<frame::Frame, failure::Error> {
+    let mut frame = frame::Frame::default();
+    if let Some(padding) = matches.value_of("padding") {
+        frame.padding = frame::Sides::parse(padding)?;
+    }
+    if let Some(color) = matches.value_of("padding_color") {
+        frame.padding_color = color.parse()?;
+    }
+    if let Some(ratio) = matches.value_of("aspect_ratio") {
+        frame.aspect_ratio = Some(frame::parse_aspect_ratio(ratio)?);
+    }
+    frame.gravity = value_t!(matches, "aspect_gravity", layout::Gravity).ok();
+    if let Some(border) = matches.value_of("border") {
+        frame.border = frame::Sides::parse(border)?;
+    }
+    if let Some(color) = matches.value_of("border_color") {
+        frame.border_color = color.parse()?;
+    }
+    Ok(frame)
+}
+
+/// Reads the rounded corners, the shape and the image masking the output.
+fn read_mask(matches: &clap::ArgMatches<'_>) -> mask::Mask {
+    mask::Mask {
+        corner_radius: value_t!(matches, "round_corners", u32).unwrap_or(0),
+        shape: value_t!(matches, "mask_shape", mask::Shape).ok(),
+        image: matches.value_of("mask_image").map(|path| {
+            (
+                std::path::PathBuf::from(path),
+                value_t!(matches, "mask_channel", mask::Channel).unwrap_or(mask::Channel::Alpha),
+            )
+        }),
+    }
+}
+
+/// Reads the blur and sharpen filters, applied in the order of the options below.
+fn read_filters(matches: &clap::ArgMatches<'_>) -> Result<filter::Filters, failure::Error> {
+    let mut filters = Vec::new();
+    if let Some(sigma) = matches.value_of("blur") {
+        filters.push(filter::Filter::GaussianBlur {
+            sigma: parse_sigma(sigma).map_err(failure::err_msg)?,
+        });
+    }
+    if let Some(radius) = matches.value_of("box_blur") {
+        filters.push(filter::Filter::BoxBlur {
+            radius: radius.parse()?,
+        });
+    }
+    if let Some(amount) = matches.value_of("sharpen") {
+        filters.push(filter::Filter::sharpen(
+            parse_factor(amount).map_err(failure::err_msg)?,
+        ));
+    }
+    if let Some(unsharp) = matches.value_of("unsharp") {
+        filters.push(filter::Filter::parse_unsharp_mask(unsharp)?);
+    }
+
+    let region = matches
+        .value_of("filter_region")
+        .map(filter::Region::parse)
+        .transpose()?;
+    Ok(filter::Filters { filters, region })
+}
+
+/// Reads `--input`, or `--canvas` with its `--background`.
+fn read_input(matches: &clap::ArgMatches<'_>) -> Result<Input, failure::Error> {
+    match (matches.value_of("input"), matches.value_of("canvas")) {
+        (_, Some(size)) => Ok(Input::Canvas {
+            size: pair::Pair::from(parse_canvas_size(size).map_err(failure::err_msg)?),
+            background: matches
+                .value_of("background")
+                .map(str::parse::<fill::Fill>)
+                .transpose()?
+                .unwrap_or_else(|| color::Color::clear().into()),
+        }),
+        (Some(path), None) => Ok(Input::File(path.to_owned())),
+        (None, None) => Err(ApplicationError::InputFileDoesNotExists(None).into()),
+    }
 }
 
 async fn dispatch(
-    input: &str,
+    input: Input,
     output: &str,
     output_format: Format,
     matches: &clap::ArgMatches<'_>,
 ) -> Result<(), failure::Error> {
-    log::info!("input: {}", input);
+    log::info!("input: {:?}", input);
     log::info!("output: {}", output);
 
-    check_file_exists(input).await?;
+    if let Input::File(path) = &input {
+        check_file_exists(path).await?;
+    }
+    check_output_format(output)?;
 
-    if let Some(pair) = matches
+    let auto_orient = !matches.is_present("no_auto_orient");
+    log::info!("auto orient: {}", auto_orient);
+    let keep_metadata = if matches.is_present("keep_metadata") {
+        metadata::Kind::ALL.to_vec()
+    } else if matches.is_present("strip_metadata") {
+        Vec::new()
+    } else {
+        match matches.value_of("keep") {
+            Some(kinds) => metadata::parse_kinds(kinds)?,
+            None => vec![metadata::Kind::Icc],
+        }
+    };
+    log::info!("keep metadata: {:?}", keep_metadata);
+    let adjustments = adjust::Adjustments {
+        brightness: value_or(matches, "brightness", parse_factor, 1.0),
+        contrast: value_or(matches, "contrast", parse_factor, 1.0),
+        gamma: value_or(matches, "gamma", parse_gamma, 1.0),
+        saturation: value_or(matches, "saturation", parse_factor, 1.0),
+        hue_rotation: value_or(matches, "hue_rotate", parse_angle, 0.0),
+    };
+    log::info!("adjustments: {:?}", adjustments);
+    let filters = read_filters(matches)?;
+    log::info!("filters: {:?}", filters);
+    let frame = read_frame(matches)?;
+    log::info!("frame: {:?}", frame);
+    let mask = read_mask(matches);
+    log::info!("mask: {:?}", mask);
+    let flatten_color = matches
+        .value_of("flatten_color")
+        .map(str::parse::<color::Color>)
+        .transpose()?
+        .unwrap_or_else(color::Color::white);
+    log::info!("flatten color: {}", flatten_color);
+    let processing = ProcessingOptions {
+        auto_orient,
+        adjustments,
+        filters,
+        frame,
+        mask,
+        keep_metadata,
+        format: output_format,
+        flatten_color,
+    };
+
+    let resize = matches
         .value_of("resize")
         .map(pair::Pair::from)
-        .or_else(|| matches.value_of("resize_keep").map(pair::Pair::from))
-    {
+        .or_else(|| matches.value_of("resize_keep").map(pair::Pair::from));
+    // `--canvas` conflicts with resizing.
+    if let (Some(pair), Some(input)) = (resize, input.path()) {
         log::info!("size: {:?}", pair);
 
         if matches.is_present("resize") {
-            resize_image(input, output, pair, output_format).await?;
+            resize_image(input, output, pair, &processing).await?;
         } else {
-            resize_image_keep_aspect_ratio(input, output, pair.x, output_format).await?;
+            resize_image_keep_aspect_ratio(input, output, pair.x, &processing).await?;
         }
-    } else if let (Some(text), Some(color), Some(font_path), Some(height)) = (
-        matches.value_of("text"),
-        matches
+    } else {
+        let text = read_text(matches).await?;
+        let overlay = matches.value_of("overlay").map(|path| overlay::Overlay {
+            path: std::path::PathBuf::from(path),
+            position: matches.value_of("overlay_position").map(pair::Pair::from),
+            gravity: value_t!(matches, "overlay_gravity", layout::Gravity).ok(),
+            scale: matches
+                .value_of("overlay_scale")
+                .and_then(|s| parse_factor(s).ok()),
+            opacity: matches
+                .value_of("overlay_opacity")
+                .and_then(|s| parse_factor(s).ok())
+                .unwrap_or(1.0),
+            rotation: matches
+                .value_of("overlay_rotate")
+                .and_then(|s| s.parse().ok()),
+            margin: matches
+                .value_of("overlay_margin")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+        });
+        let tiling = if matches.is_present("tile") {
+            Some(overlay::Tiling {
+                spacing: matches.value_of("tile_spacing").map(pair::Pair::from),
+                stagger: matches.is_present("tile_stagger"),
+                opacity: matches
+                    .value_of("tile_opacity")
+                    .and_then(|s| parse_factor(s).ok())
+                    .unwrap_or(1.0),
+            })
+        } else {
+            None
+        };
+        if text.is_none()
+            && overlay.is_none()
+            && processing.adjustments.is_identity()
+            && processing.filters.is_empty()
+            && processing.frame.is_empty()
+            && processing.mask.is_empty()
+            && input.path().is_some()
+        {
+            return Ok(());
+        }
+
+        let color = matches
             .value_of("color")
-            .map(color::Color::from)
-            .or_else(|| Some(color::Color::black())),
-        matches.value_of("font"),
-        matches
+            .map(str::parse::<fill::Fill>)
+            .transpose()?
+            .unwrap_or_else(|| color::Color::black().into());
+        let height = matches
             .value_of("font_height")
-            .map(|s| s.parse::<u32>().unwrap_or(12)),
-    ) {
-        let shadow_color = matches.value_of("shadow_color").map(color::Color::from);
+            .map(|s| s.parse::<u32>().unwrap_or(12));
+        let shadow_color = matches
+            .value_of("shadow_color")
+            .map(str::parse::<fill::Fill>)
+            .transpose()?;
+        let text_background = matches
+            .value_of("text_background")
+            .map(str::parse::<fill::Fill>)
+            .transpose()?;
+        let text_background_padding = matches
+            .value_of("text_background_padding")
+            .map(str::parse::<u32>)
+            .transpose()?;
         let position = matches.value_of("position").map(pair::Pair::from);
-        let gravity = value_t!(matches, "gravity", Gravity).ok();
+        let gravity = value_t!(matches, "gravity", layout::Gravity).ok();
+        let mut layout_options = layout::LayoutOptions::default();
+        if matches.is_present("vertical") {
+            layout_options.writing_mode = layout::WritingMode::Vertical;
+        }
+        if let Some(line_height) = matches.value_of("line_height") {
+            layout_options.line_height = parse_factor(line_height).map_err(failure::err_msg)?;
+        }
+        if let Some(letter_spacing) = matches.value_of("letter_spacing") {
+            layout_options.letter_spacing = letter_spacing.parse()?;
+        }
+        if let Some(paragraph_spacing) = matches.value_of("paragraph_spacing") {
+            layout_options.paragraph_spacing =
+                parse_factor(paragraph_spacing).map_err(failure::err_msg)?;
+        }
+        let mut styles = decoration::Styles::default();
+        for spec in matches.values_of("style").into_iter().flatten() {
+            styles.apply(spec)?;
+        }
 
-        log::info!("text: {}", text);
+        log::info!("text: {:?}", text);
+        log::info!("markup: {}", !matches.is_present("no_markup"));
+        log::info!("template: {}", matches.is_present("template"));
         log::info!("color: {:?}", color);
         log::info!("shadow color: {:?}", shadow_color);
-        log::info!("font path: {}", font_path);
-        log::info!("font height: {}", height);
+        log::info!("text background: {:?}", text_background);
+        log::info!("text background padding: {:?}", text_background_padding);
+        let mut fonts = matches
+            .values_of("font")
+            .into_iter()
+            .flatten()
+            .map(font::FontSource::from)
+            .collect::<Vec<_>>();
+        if let Some(family) = matches.value_of("font_family") {
+            let weight = matches
+                .value_of("font_weight")
+                .and_then(lookup::parse_weight)
+                .unwrap_or(lookup::REGULAR_WEIGHT);
+            let font_dirs = matches
+                .values_of("font_dir")
+                .into_iter()
+                .flatten()
+                .map(std::path::PathBuf::from)
+                .collect::<Vec<_>>();
+            let font_dirs = lookup::font_dirs(&font_dirs);
+
+            log::info!("font family: {}", family);
+            log::info!("font weight: {}", weight);
+            log::info!("font directories: {:?}", font_dirs);
+
+            match lookup::resolve(family, weight, &font_dirs).await? {
+                Some(source) => fonts.insert(0, source),
+                None if !fonts.is_empty() || font::HAS_EMBEDDED_FONT => {
+                    log::warn!(
+                        "font family {:?} is not found. using fallback fonts.",
+                        family
+                    )
+                }
+                None => return Err(ApplicationError::FontFamilyNotFound(family.to_owned()).into()),
+            }
+        }
+        let font_face = value_t!(matches, "font_index", usize)
+            .ok()
+            .map(font::Face::Index)
+            .or_else(|| {
+                matches
+                    .value_of("font_name")
+                    .map(|name| font::Face::Name(name.to_owned()))
+            });
+        log::info!("fonts: {:?}", fonts);
+        log::info!("font face: {:?}", font_face);
+        let font_features = match matches.value_of("font_features") {
+            Some(features) => shaping::parse_features(features)?,
+            None => Vec::new(),
+        };
+        log::info!("font features: {:?}", font_features);
+        log::info!("font height: {:?}", height);
         log::info!("position: {:?}", position);
         log::info!("gravity: {:?}", gravity);
+        log::info!("layout: {:?}", layout_options);
+        let rotation = matches
+            .value_of("text_rotate")
+            .map(str::parse::<f32>)
+            .transpose()?;
+        log::info!("text rotation: {:?}", rotation);
+        log::info!("styles: {:?}", styles);
+        log::info!("overlay: {:?}", overlay);
+        log::info!("tiling: {:?}", tiling);
 
         let options = DrawingOptions {
-            in_path: input.to_owned(),
+            input,
             out_path: output.to_owned(),
-            text: text.to_owned(),
+            processing,
+            text,
+            markup: !matches.is_present("no_markup"),
+            template: matches.is_present("template"),
             color,
             shadow_color,
-            font_path: font_path.to_owned(),
+            text_background,
+            text_background_padding,
+            fonts,
+            font_face,
+            font_features,
+            styles,
             height,
             position,
             gravity,
-            format: output_format,
+            layout: layout_options,
+            rotation,
+            overlay,
+            tiling,
         };
 
         if matches.is_present("grayscale") {
-            draw_text_luma_alpha(options).await?;
+            draw_luma_alpha(options).await?;
         } else {
-            draw_text_rgba(options).await?;
+            draw_rgba(options).await?;
         }
     }
 
@@ -561,10 +1660,15 @@ async fn run() -> Result<(), failure::Error> {
     let app = add_options_to_app(app);
     let matches = app.get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("fonts") {
+        return list_fonts(matches).await;
+    }
+
     let output_format = value_t!(matches, "format", Format).unwrap_or(Format::Png);
     log::info!("output format: {:?}", output_format);
 
-    if let (Some(input), Some(output)) = (matches.value_of("input"), matches.value_of("output")) {
+    if let Some(output) = matches.value_of("output") {
+        let input = read_input(&matches)?;
         dispatch(input, output, output_format, &matches).await?;
     }
 
@@ -578,3 +1682,131 @@ async fn main() {
         Err(error) => log::error!("{:?}", error),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_output_format() {
+        assert!(check_output_format("out.png").is_ok());
+        assert!(check_output_format("out").is_ok());
+        assert!(check_output_format("out.webp").is_err());
+        assert!(check_output_format("dir/OUT.WebP").is_err());
+    }
+
+    #[test]
+    fn test_text_layer_size() {
+        assert_eq!(
+            text_layer_size((100, 20), 5, (400, 200)).unwrap(),
+            (110, 30)
+        );
+        assert!(text_layer_size((100, 20), 100_000, (400, 200)).is_err());
+        assert!(text_layer_size((100, 20), u32::max_value(), (400, 200)).is_err());
+        assert_eq!(
+            text_layer_size((100, 20), 7_000, (30_000, 40_000)).unwrap(),
+            (14_100, 14_020)
+        );
+    }
+
+    #[cfg(feature = "embedded-font")]
+    #[test]
+    fn test_translucent_text_keeps_opaque_alpha() {
+        let chain = font::FontChain::new(font::embedded(), Vec::new());
+        let (glyphs, _) = chain.layout("Ag", rusttype::Scale::uniform(40.0), 0.0);
+        let color: color::Color = "rgba(0, 0, 0, 0.5)".parse().unwrap();
+
+        let mut rgba = image::RgbaImage::from_pixel(80, 60, image::Rgba([255, 255, 255, 255]));
+        font::fill_glyphs_mut(&mut rgba, |_, _| color.into(), 10, 40, &glyphs);
+        assert!(rgba.pixels().all(|p| p[3] == 255));
+        assert!(rgba.pixels().any(|p| p[0] < 255));
+        assert!(rgba.pixels().all(|p| p[0] >= 127));
+
+        let mut luma_alpha = image::GrayAlphaImage::from_pixel(80, 60, image::LumaA([255, 255]));
+        font::fill_glyphs_mut(&mut luma_alpha, |_, _| color.into(), 10, 40, &glyphs);
+        assert!(luma_alpha.pixels().all(|p| p[1] == 255));
+        assert!(luma_alpha.pixels().any(|p| p[0] < 255));
+    }
+
+    #[cfg(feature = "embedded-font")]
+    #[test]
+    fn test_layer_text_edges() {
+        let chain = font::FontChain::new(font::embedded(), Vec::new());
+        let (glyphs, _) = chain.layout("Ag", rusttype::Scale::uniform(40.0), 0.0);
+        let white = |_, _| image::Rgba([255, 255, 255, 255]);
+        let red = image::Rgba([255, 0, 0, 255]);
+
+        let mut direct = image::RgbaImage::from_pixel(80, 60, red);
+        font::fill_glyphs_mut(&mut direct, white, 10, 40, &glyphs);
+
+        let transparent = image::Rgba([0, 0, 0, 0]);
+        let mut layer = image::RgbaImage::from_pixel(80, 60, transparent);
+        font::fill_glyphs_mut(&mut layer, white, 10, 40, &glyphs);
+        let layer = overlay::rotate(&layer, 0.0, transparent);
+        let mut layered = image::RgbaImage::from_pixel(80, 60, red);
+        overlay::composite(&mut layered, &layer, (0, 0));
+
+        let edges = direct
+            .enumerate_pixels()
+            .filter(|(_, _, p)| p[1] > 0 && p[1] < 255)
+            .collect::<Vec<_>>();
+        assert!(!edges.is_empty());
+        for (x, y, pixel) in edges {
+            let layered = layered.get_pixel(x, y);
+            assert_eq!(layered[0], 255, "{:?}", (x, y, layered));
+            assert!((i32::from(layered[1]) - i32::from(pixel[1])).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_flatten_luma_alpha() {
+        let mut image = image::GrayAlphaImage::from_pixel(2, 1, image::LumaA([0, 255]));
+        image.put_pixel(1, 0, image::LumaA([0, 0]));
+        let (flattened, transparent) = flatten(
+            &image::DynamicImage::ImageLumaA8(image),
+            color::Color::white(),
+        )
+        .unwrap();
+
+        assert_eq!(transparent, 1);
+        let flattened = flattened.as_luma8().unwrap();
+        assert_eq!(flattened.get_pixel(0, 0), &image::Luma([0]));
+        assert_eq!(flattened.get_pixel(1, 0), &image::Luma([255]));
+    }
+
+    #[test]
+    fn test_flatten_rgba() {
+        let mut image = image::RgbaImage::from_pixel(3, 1, image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([255, 0, 0, 0]));
+        image.put_pixel(2, 0, image::Rgba([255, 0, 0, 128]));
+        let (flattened, transparent) = flatten(
+            &image::DynamicImage::ImageRgba8(image),
+            "blue".parse().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(transparent, 2);
+        let flattened = flattened.as_rgb8().unwrap();
+        assert_eq!(flattened.get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+        assert_eq!(flattened.get_pixel(1, 0), &image::Rgb([0, 0, 255]));
+        let image::Rgb([r, g, b]) = *flattened.get_pixel(2, 0);
+        assert!(r > 100 && g == 0 && b > 100, "{:?}", (r, g, b));
+    }
+
+    #[test]
+    fn test_flatten_opaque() {
+        let image = image::RgbaImage::from_fn(2, 2, |x, y| image::Rgba([x as u8, y as u8, 7, 255]));
+        let (flattened, transparent) = flatten(
+            &image::DynamicImage::ImageRgba8(image.clone()),
+            color::Color::black(),
+        )
+        .unwrap();
+        assert_eq!(transparent, 0);
+        for (x, y, pixel) in flattened.as_rgb8().unwrap().enumerate_pixels() {
+            assert_eq!(pixel.0[..], image.get_pixel(x, y).0[..3]);
+        }
+
+        let rgb = image::DynamicImage::new_rgb8(2, 2);
+        assert!(flatten(&rgb, color::Color::black()).is_none());
+    }
+}
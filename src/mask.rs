@@ -0,0 +1,214 @@
+use clap::arg_enum;
+use std::path::PathBuf;
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Shape {
+        Circle,
+        Ellipse,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Channel {
+        Alpha,
+        Luminance,
+    }
+}
+
+/// Makes the outside of a shape transparent, such as the corners of a card or an avatar.
+///
+/// The shapes are antialiased and all of them are combined by multiplying the alpha channel.
+#[derive(Debug, Clone, Default)]
+pub struct Mask {
+    /// Radius in pixels of the rounded corners.
+    pub corner_radius: u32,
+    /// A circle or an ellipse inscribed in the image.
+    pub shape: Option<Shape>,
+    /// An image scaled to the size of the image whose `Channel` becomes the alpha channel.
+    pub image: Option<(PathBuf, Channel)>,
+}
+
+impl Mask {
+    pub fn is_empty(&self) -> bool {
+        self.corner_radius == 0 && self.shape.is_none() && self.image.is_none()
+    }
+
+    /// Masks an image whose last channel is alpha.
+    pub fn apply<P>(&self, image: &mut image::ImageBuffer<P, Vec<u8>>) -> Result<(), failure::Error>
+    where
+        P: image::Pixel<Subpixel = u8> + 'static,
+    {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let size = image.dimensions();
+        let values = match &self.image {
+            Some((path, channel)) => Some(values(&image::open(path)?, *channel, size)),
+            None => None,
+        };
+
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let point = (x as f32 + 0.5, y as f32 + 0.5);
+            let mut coverage = 1.0;
+            if self.corner_radius > 0 {
+                coverage *= rounded_rectangle(point, size, self.corner_radius as f32);
+            }
+            match self.shape {
+                Some(Shape::Circle) => coverage *= circle(point, size),
+                Some(Shape::Ellipse) => coverage *= ellipse(point, size),
+                None => {}
+            }
+            if let Some(values) = &values {
+                coverage *= f32::from(values.get_pixel(x, y)[0]) / 255.0;
+            }
+
+            if let Some(alpha) = pixel.channels_mut().last_mut() {
+                *alpha = (f32::from(*alpha) * coverage).round() as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Masks a decoded image of any color type, which becomes RGBA or grayscale with alpha.
+    pub fn apply_dynamic(
+        &self,
+        image: image::DynamicImage,
+    ) -> Result<image::DynamicImage, failure::Error> {
+        use image::DynamicImage::*;
+
+        if self.is_empty() {
+            return Ok(image);
+        }
+        match image {
+            ImageLuma8(_) | ImageLumaA8(_) => {
+                let mut buffer = image.to_luma_alpha();
+                self.apply(&mut buffer)?;
+                Ok(ImageLumaA8(buffer))
+            }
+            image => {
+                let mut buffer = image.to_rgba();
+                self.apply(&mut buffer)?;
+                Ok(ImageRgba8(buffer))
+            }
+        }
+    }
+}
+
+/// Returns `channel` of `mask` scaled to `size`.
+fn values(mask: &image::DynamicImage, channel: Channel, size: (u32, u32)) -> image::GrayImage {
+    let values = match channel {
+        Channel::Alpha => {
+            let rgba = mask.to_rgba();
+            image::GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                image::Luma([rgba.get_pixel(x, y)[3]])
+            })
+        }
+        Channel::Luminance => mask.to_luma(),
+    };
+    if values.dimensions() == size {
+        return values;
+    }
+    image::imageops::resize(&values, size.0, size.1, image::FilterType::Triangle)
+}
+
+/// Converts a signed distance from an edge in pixels, negative inside, to the covered fraction of a pixel.
+fn coverage(distance: f32) -> f32 {
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+fn rounded_rectangle((x, y): (f32, f32), (width, height): (u32, u32), radius: f32) -> f32 {
+    let (width, height) = (width as f32, height as f32);
+    let radius = radius.min(width / 2.0).min(height / 2.0);
+    let dx = (radius - x).max(x - (width - radius)).max(0.0);
+    let dy = (radius - y).max(y - (height - radius)).max(0.0);
+    if dx > 0.0 && dy > 0.0 {
+        coverage(dx.hypot(dy) - radius)
+    } else {
+        1.0
+    }
+}
+
+fn circle((x, y): (f32, f32), (width, height): (u32, u32)) -> f32 {
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    coverage((x - cx).hypot(y - cy) - cx.min(cy))
+}
+
+fn ellipse((x, y): (f32, f32), (width, height): (u32, u32)) -> f32 {
+    let (a, b) = (width as f32 / 2.0, height as f32 / 2.0);
+    let (u, v) = ((x - a) / a, (y - b) / b);
+    // The implicit function divided by the length of its gradient approximates the distance.
+    let gradient = 2.0 * (u * u / (a * a) + v * v / (b * b)).sqrt();
+    if gradient == 0.0 {
+        return 1.0;
+    }
+    coverage((u * u + v * v - 1.0) / gradient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(mask: Mask) -> image::RgbaImage {
+        let mut image = image::RgbaImage::from_pixel(40, 20, image::Rgba([255, 0, 0, 255]));
+        mask.apply(&mut image).unwrap();
+        image
+    }
+
+    #[test]
+    fn test_rounded_corners() {
+        let image = mask(Mask {
+            corner_radius: 8,
+            ..Mask::default()
+        });
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        assert_eq!(image.get_pixel(39, 19)[3], 0);
+        assert_eq!(image.get_pixel(20, 0)[3], 255);
+        assert_eq!(image.get_pixel(0, 10)[3], 255);
+        let edge = image.get_pixel(2, 2)[3];
+        assert!(edge > 0 && edge < 255, "{}", edge);
+    }
+
+    #[test]
+    fn test_circle_and_ellipse() {
+        let image = mask(Mask {
+            shape: Some(Shape::Circle),
+            ..Mask::default()
+        });
+        assert_eq!(image.get_pixel(20, 10)[3], 255);
+        assert_eq!(image.get_pixel(20, 1)[3], 255);
+        assert_eq!(image.get_pixel(5, 10)[3], 0);
+
+        let image = mask(Mask {
+            shape: Some(Shape::Ellipse),
+            ..Mask::default()
+        });
+        assert_eq!(image.get_pixel(5, 10)[3], 255);
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        let edge = image.get_pixel(0, 10)[3];
+        assert!(edge > 0 && edge < 255, "{}", edge);
+        let edge = image.get_pixel(1, 5)[3];
+        assert!(edge > 0 && edge < 128, "{}", edge);
+    }
+
+    #[test]
+    fn test_values() {
+        let mut mask = image::RgbaImage::from_pixel(2, 1, image::Rgba([255, 255, 255, 0]));
+        mask.put_pixel(1, 0, image::Rgba([0, 0, 0, 255]));
+        let mask = image::DynamicImage::ImageRgba8(mask);
+
+        let alpha = values(&mask, Channel::Alpha, (2, 1));
+        assert_eq!(
+            (alpha.get_pixel(0, 0)[0], alpha.get_pixel(1, 0)[0]),
+            (0, 255)
+        );
+        let luminance = values(&mask, Channel::Luminance, (2, 1));
+        assert_eq!(
+            (luminance.get_pixel(0, 0)[0], luminance.get_pixel(1, 0)[0]),
+            (255, 0)
+        );
+        assert_eq!(values(&mask, Channel::Alpha, (4, 3)).dimensions(), (4, 3));
+    }
+}
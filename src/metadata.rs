@@ -0,0 +1,472 @@
+use failure::Fail;
+use std::path::Path;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const RIFF_SIGNATURE: &[u8] = b"RIFF";
+const WEBP_SIGNATURE: &[u8] = b"WEBP";
+
+/// The largest payload of a JPEG segment.
+const SEGMENT_SIZE: usize = 65533;
+
+#[derive(Debug, Clone, Fail)]
+pub enum MetadataError {
+    #[fail(display = "unknown metadata kind: {}", _0)]
+    UnknownKind(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Exif,
+    Icc,
+    Xmp,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 3] = [Kind::Exif, Kind::Icc, Kind::Xmp];
+}
+
+/// Parses comma separated metadata kinds such as `exif,icc`.
+pub fn parse_kinds(s: &str) -> Result<Vec<Kind>, MetadataError> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(|k| match k.to_lowercase().as_str() {
+            "exif" => Ok(Kind::Exif),
+            "icc" => Ok(Kind::Icc),
+            "xmp" => Ok(Kind::Xmp),
+            _ => Err(MetadataError::UnknownKind(k.to_owned())),
+        })
+        .collect()
+}
+
+/// Metadata carried from an input image to its output.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// The TIFF structure of the EXIF data.
+    pub exif: Option<Vec<u8>>,
+    pub icc: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Reads the metadata of `keep` kinds from a JPEG, PNG or WebP file.
+    ///
+    /// The EXIF orientation is reset if the pixels are made upright with `auto_orient`.
+    pub fn load<P>(path: P, keep: &[Kind], auto_orient: bool) -> Metadata
+    where
+        P: AsRef<Path>,
+    {
+        if keep.is_empty() {
+            return Metadata::default();
+        }
+
+        let mut metadata = match std::fs::read(&path) {
+            Ok(data) => Metadata::read(&data),
+            Err(error) => {
+                log::warn!("failed to read metadata: {}", error);
+                return Metadata::default();
+            }
+        };
+        metadata.retain(keep);
+        if auto_orient {
+            if let Some(exif) = &mut metadata.exif {
+                reset_orientation(exif);
+            }
+        }
+        metadata
+    }
+
+    /// Reads the metadata of JPEG, PNG or WebP data. Other formats have none.
+    pub fn read(data: &[u8]) -> Metadata {
+        if data.starts_with(PNG_SIGNATURE) {
+            read_png(data)
+        } else if data.starts_with(&[0xff, 0xd8]) {
+            read_jpeg(data)
+        } else if data.starts_with(RIFF_SIGNATURE) && data.get(8..12) == Some(WEBP_SIGNATURE) {
+            read_webp(data)
+        } else {
+            Metadata::default()
+        }
+    }
+
+    pub fn retain(&mut self, keep: &[Kind]) {
+        if !keep.contains(&Kind::Exif) {
+            self.exif = None;
+        }
+        if !keep.contains(&Kind::Icc) {
+            self.icc = None;
+        }
+        if !keep.contains(&Kind::Xmp) {
+            self.xmp = None;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.icc.is_none() && self.xmp.is_none()
+    }
+
+    /// Inserts the metadata into encoded JPEG or PNG data, the formats of outputs.
+    pub fn embed(&self, data: Vec<u8>) -> Vec<u8> {
+        if self.is_empty() {
+            data
+        } else if data.starts_with(PNG_SIGNATURE) {
+            self.embed_png(data)
+        } else if data.starts_with(&[0xff, 0xd8]) {
+            self.embed_jpeg(data)
+        } else {
+            data
+        }
+    }
+
+    fn embed_jpeg(&self, data: Vec<u8>) -> Vec<u8> {
+        let mut segments = Vec::new();
+        if let Some(exif) = &self.exif {
+            segments.push((0xe1, [EXIF_HEADER, exif].concat()));
+        }
+        if let Some(xmp) = &self.xmp {
+            segments.push((0xe1, [XMP_HEADER, xmp].concat()));
+        }
+        if let Some(icc) = &self.icc {
+            let chunks = icc
+                .chunks(SEGMENT_SIZE - ICC_HEADER.len() - 2)
+                .collect::<Vec<_>>();
+            for (index, chunk) in chunks.iter().enumerate() {
+                let numbers = [index as u8 + 1, chunks.len() as u8];
+                segments.push((0xe2, [ICC_HEADER, &numbers, chunk].concat()));
+            }
+        }
+
+        // Keep the JFIF segment first if the encoder wrote one.
+        let mut position = 2;
+        if data.get(2..4) == Some(&[0xff, 0xe0]) {
+            position += 2 + u16_be(&data[4..6]) as usize;
+        }
+
+        let mut embedded = data[..position].to_vec();
+        for (marker, payload) in segments {
+            if payload.len() > SEGMENT_SIZE {
+                log::warn!(
+                    "metadata segment is too large for JPEG: {} bytes",
+                    payload.len()
+                );
+                continue;
+            }
+            embedded.extend_from_slice(&[0xff, marker]);
+            embedded.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+            embedded.extend_from_slice(&payload);
+        }
+        embedded.extend_from_slice(&data[position..]);
+        embedded
+    }
+
+    fn embed_png(&self, data: Vec<u8>) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        if let Some(icc) = &self.icc {
+            let profile = [b"ICC Profile\0\0", &deflate::deflate_bytes_zlib(icc)[..]].concat();
+            chunks.push(png_chunk(b"iCCP", &profile));
+        }
+        if let Some(exif) = &self.exif {
+            chunks.push(png_chunk(b"eXIf", exif));
+        }
+        if let Some(xmp) = &self.xmp {
+            let text = [XMP_KEYWORD, b"\0\0\0\0\0", xmp].concat();
+            chunks.push(png_chunk(b"iTXt", &text));
+        }
+
+        // IHDR is always the first chunk: length, type, 13 bytes of data and CRC.
+        let position = PNG_SIGNATURE.len() + 4 + 4 + 13 + 4;
+        let mut embedded = data[..position].to_vec();
+        for chunk in chunks {
+            embedded.extend_from_slice(&chunk);
+        }
+        embedded.extend_from_slice(&data[position..]);
+        embedded
+    }
+}
+
+fn u16_be(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_jpeg(data: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut icc_chunks = Vec::new();
+    let mut position = 2;
+
+    while position + 4 <= data.len() && data[position] == 0xff {
+        let marker = data[position + 1];
+        // Start of scan: only image data follows.
+        if marker == 0xda {
+            break;
+        }
+        let length = u16_be(&data[position + 2..]) as usize;
+        let end = (position + 2 + length).min(data.len());
+        let payload = &data[(position + 4).min(end)..end];
+
+        match marker {
+            0xe1 if payload.starts_with(EXIF_HEADER) => {
+                metadata.exif = Some(payload[EXIF_HEADER.len()..].to_vec());
+            }
+            0xe1 if payload.starts_with(XMP_HEADER) => {
+                metadata.xmp = Some(payload[XMP_HEADER.len()..].to_vec());
+            }
+            0xe2 if payload.starts_with(ICC_HEADER) && payload.len() >= ICC_HEADER.len() + 2 => {
+                let index = payload[ICC_HEADER.len()];
+                icc_chunks.push((index, &payload[ICC_HEADER.len() + 2..]));
+            }
+            _ => {}
+        }
+        position = end;
+    }
+
+    if !icc_chunks.is_empty() {
+        icc_chunks.sort_by_key(|(index, _)| *index);
+        metadata.icc = Some(
+            icc_chunks
+                .into_iter()
+                .flat_map(|(_, c)| c.to_vec())
+                .collect(),
+        );
+    }
+    metadata
+}
+
+fn read_png(data: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut position = PNG_SIGNATURE.len();
+
+    while position + 8 <= data.len() {
+        let length = u32_be(&data[position..]) as usize;
+        let kind = &data[position + 4..position + 8];
+        let end = (position + 8 + length).min(data.len());
+        let chunk = &data[position + 8..end];
+
+        match kind {
+            b"eXIf" => metadata.exif = Some(chunk.to_vec()),
+            b"iCCP" => {
+                // Profile name, a null separator and the compression method precede the profile.
+                if let Some(name_end) = chunk.iter().position(|b| *b == 0) {
+                    let profile = chunk.get(name_end + 2..).unwrap_or_default();
+                    match inflate::inflate_bytes_zlib(profile) {
+                        Ok(icc) => metadata.icc = Some(icc),
+                        Err(error) => log::warn!("broken ICC profile: {}", error),
+                    }
+                }
+            }
+            b"iTXt" if chunk.starts_with(XMP_KEYWORD) => metadata.xmp = read_itxt(chunk),
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+        position = end + 4;
+    }
+    metadata
+}
+
+fn read_webp(data: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut position = RIFF_SIGNATURE.len() + 4 + WEBP_SIGNATURE.len();
+
+    while position + 8 <= data.len() {
+        let kind = &data[position..position + 4];
+        let length = u32_le(&data[position + 4..]) as usize;
+        let end = (position + 8).saturating_add(length).min(data.len());
+        let chunk = &data[position + 8..end];
+
+        match kind {
+            // Some writers keep the header of the JPEG segment.
+            b"EXIF" => {
+                metadata.exif = Some(chunk.strip_prefix(EXIF_HEADER).unwrap_or(chunk).to_vec())
+            }
+            b"ICCP" => metadata.icc = Some(chunk.to_vec()),
+            b"XMP " => metadata.xmp = Some(chunk.to_vec()),
+            _ => {}
+        }
+        // Chunks are padded to an even size.
+        position = end + (length & 1);
+    }
+    metadata
+}
+
+/// Reads the text of an iTXt chunk.
+fn read_itxt(chunk: &[u8]) -> Option<Vec<u8>> {
+    let mut fields = chunk.splitn(2, |b| *b == 0);
+    fields.next()?;
+    let rest = fields.next()?;
+    let (compressed, rest) = (*rest.first()? == 1, rest.get(2..)?);
+    // Skip the language tag and the translated keyword.
+    let mut fields = rest.splitn(3, |b| *b == 0);
+    fields.next()?;
+    fields.next()?;
+    let text = fields.next()?;
+
+    if compressed {
+        inflate::inflate_bytes_zlib(text).ok()
+    } else {
+        Some(text.to_vec())
+    }
+}
+
+fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+    chunk
+}
+
+/// Sets the Orientation tag of the first IFD in EXIF data to 1 (upright).
+pub fn reset_orientation(exif: &mut [u8]) {
+    let big_endian = match exif.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return,
+    };
+    let read_u16 = |b: &[u8]| {
+        if big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        }
+    };
+    let ifd = match exif.get(4..8) {
+        Some(b) if big_endian => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize,
+        Some(b) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize,
+        None => return,
+    };
+    let count = match exif.get(ifd..ifd + 2) {
+        Some(b) => read_u16(b) as usize,
+        None => return,
+    };
+
+    for entry in (0..count).map(|i| ifd + 2 + i * 12) {
+        let (tag, kind) = match exif.get(entry..entry + 12) {
+            Some(e) => (read_u16(&e[0..2]), read_u16(&e[2..4])),
+            None => return,
+        };
+        // The Orientation tag holds a SHORT.
+        if tag == 0x0112 && kind == 3 {
+            let value = if big_endian {
+                1u16.to_be_bytes()
+            } else {
+                1u16.to_le_bytes()
+            };
+            exif[entry + 8..entry + 10].copy_from_slice(&value);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A big-endian TIFF structure with the Orientation tag set to 6.
+    fn exif() -> Vec<u8> {
+        let mut exif = b"MM\0*\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        exif
+    }
+
+    fn metadata() -> Metadata {
+        Metadata {
+            exif: Some(exif()),
+            icc: Some(vec![7; 70000]),
+            xmp: Some(b"<x:xmpmeta/>".to_vec()),
+        }
+    }
+
+    fn encode(format: image::ImageOutputFormat) -> Vec<u8> {
+        let image = image::DynamicImage::new_rgb8(2, 2);
+        let mut data = Vec::new();
+        image.write_to(&mut data, format).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_parse_kinds() {
+        assert_eq!(
+            parse_kinds("exif, ICC").unwrap(),
+            vec![Kind::Exif, Kind::Icc]
+        );
+        assert!(parse_kinds("exif,gps").is_err());
+    }
+
+    #[test]
+    fn test_jpeg_round_trip() {
+        let data = metadata().embed(encode(image::ImageOutputFormat::JPEG(90)));
+        assert_eq!(Metadata::read(&data), metadata());
+        assert!(image::load_from_memory(&data).is_ok());
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let data = metadata().embed(encode(image::ImageOutputFormat::PNG));
+        assert_eq!(Metadata::read(&data), metadata());
+        assert!(image::load_from_memory(&data).is_ok());
+    }
+
+    #[test]
+    fn test_read_webp() {
+        let chunk = |kind: &[u8], data: &[u8]| {
+            let mut chunk = kind.to_vec();
+            chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            chunk.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        };
+        let body = [
+            &b"WEBP"[..],
+            &chunk(b"VP8X", &[0x2c, 0, 0, 0, 1, 0, 0, 1, 0, 0]),
+            &chunk(b"ICCP", &[7; 3]),
+            &chunk(b"VP8L", &[0; 5]),
+            &chunk(b"EXIF", &[EXIF_HEADER, &exif()].concat()),
+            &chunk(b"XMP ", b"<x:xmpmeta/>"),
+        ]
+        .concat();
+        let data = [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat();
+
+        assert_eq!(
+            Metadata::read(&data),
+            Metadata {
+                exif: Some(exif()),
+                icc: Some(vec![7; 3]),
+                xmp: Some(b"<x:xmpmeta/>".to_vec()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut metadata = metadata();
+        metadata.retain(&[Kind::Icc]);
+        assert!(metadata.exif.is_none() && metadata.xmp.is_none());
+        assert!(metadata.icc.is_some());
+    }
+
+    #[test]
+    fn test_reset_orientation() {
+        let mut exif = exif();
+        reset_orientation(&mut exif);
+        assert_eq!(&exif[18..20], &[0, 1]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::Path;
+
+/// Reads the EXIF Orientation tag (1 to 8) of an image file.
+pub fn read<P>(path: P) -> Option<u32>
+where
+    P: AsRef<Path>,
+{
+    let file = std::fs::File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .ok()?;
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+/// Transforms `image` stored with EXIF `orientation` so that it is upright.
+pub fn apply(image: image::DynamicImage, orientation: u32) -> image::DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Opens an image, making it upright by its EXIF orientation if `auto_orient` is set.
+pub fn open<P>(path: P, auto_orient: bool) -> Result<image::DynamicImage, failure::Error>
+where
+    P: AsRef<Path>,
+{
+    let image = image::open(&path)?;
+    if !auto_orient {
+        return Ok(image);
+    }
+
+    match read(&path) {
+        Some(orientation) if orientation != 1 => {
+            log::info!("EXIF orientation: {}", orientation);
+            Ok(apply(image, orientation))
+        }
+        _ => Ok(image),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    /// A 2x1 image with a black pixel on the left and a white one on the right.
+    fn image() -> image::DynamicImage {
+        let mut image = image::GrayImage::new(2, 1);
+        image.put_pixel(1, 0, image::Luma([255]));
+        image::DynamicImage::ImageLuma8(image)
+    }
+
+    fn white_pixel(image: &image::DynamicImage) -> (u32, u32) {
+        image
+            .pixels()
+            .find(|(_, _, p)| p[0] == 255)
+            .map(|(x, y, _)| (x, y))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply_mirrored() {
+        assert_eq!(white_pixel(&apply(image(), 1)), (1, 0));
+        assert_eq!(white_pixel(&apply(image(), 2)), (0, 0));
+    }
+
+    #[test]
+    fn test_apply_rotated() {
+        let rotated = apply(image(), 6);
+        assert_eq!(rotated.dimensions(), (1, 2));
+        assert_eq!(white_pixel(&rotated), (0, 1));
+        assert_eq!(white_pixel(&apply(image(), 8)), (0, 0));
+    }
+
+    #[test]
+    fn test_apply_transposed() {
+        assert_eq!(white_pixel(&apply(image(), 5)), (0, 1));
+        assert_eq!(white_pixel(&apply(image(), 7)), (0, 0));
+    }
+}
@@ -0,0 +1,332 @@
+use crate::filter;
+use crate::layout::{self, Gravity};
+use crate::pair::Pair;
+use std::path::PathBuf;
+
+/// An image stamped onto the canvas, such as a logo.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    pub path: PathBuf,
+    pub position: Option<Pair<u32>>,
+    pub gravity: Option<Gravity>,
+    /// The width of the overlay as a percentage of the canvas width. The original size is kept if `None`.
+    pub scale: Option<f32>,
+    pub opacity: f32,
+    /// Degrees to rotate the overlay clockwise around its center.
+    pub rotation: Option<f32>,
+    /// Space kept between the overlay and the canvas edges when placed by gravity.
+    pub margin: u32,
+}
+
+impl Overlay {
+    /// Loads the overlay image scaled for a canvas of `canvas_size` with its opacity applied.
+    pub fn load(&self, canvas_size: (u32, u32)) -> Result<image::DynamicImage, failure::Error> {
+        let mut overlay = image::open(&self.path)?.to_rgba();
+
+        if let Some(scale) = self.scale {
+            let width = ((canvas_size.0 as f32 * scale / 100.0).round() as u32).max(1);
+            let height = ((overlay.height() as f32 * width as f32 / overlay.width() as f32).round()
+                as u32)
+                .max(1);
+            overlay = image::imageops::resize(&overlay, width, height, image::FilterType::Lanczos3);
+        }
+
+        if let Some(degrees) = self.rotation {
+            overlay = rotate(&overlay, degrees, image::Rgba([0, 0, 0, 0]));
+        }
+        fade(&mut overlay, self.opacity);
+
+        Ok(image::DynamicImage::ImageRgba8(overlay))
+    }
+
+    /// Returns the top-left corner of an overlay of `size` on a canvas of `canvas_size`.
+    pub fn origin(&self, canvas_size: (u32, u32), size: (u32, u32)) -> (i32, i32) {
+        let margin = self.margin.min(canvas_size.0 / 2).min(canvas_size.1 / 2);
+        let inner_size = (canvas_size.0 - margin * 2, canvas_size.1 - margin * 2);
+        let (x, y) = layout::place(
+            self.position,
+            self.gravity,
+            inner_size,
+            (size.0 as i32, size.1 as i32),
+        );
+
+        match self.position {
+            Some(_) => (x, y),
+            None => (x + margin as i32, y + margin as i32),
+        }
+    }
+}
+
+/// Blends `layer` over `canvas` with its top-left corner at `(x, y)`, clipping it to the canvas.
+pub fn composite<P>(
+    canvas: &mut image::ImageBuffer<P, Vec<u8>>,
+    layer: &image::ImageBuffer<P, Vec<u8>>,
+    (x, y): (i32, i32),
+) where
+    P: image::Pixel<Subpixel = u8> + 'static,
+{
+    let (width, height) = canvas.dimensions();
+
+    for (layer_x, layer_y, pixel) in layer.enumerate_pixels() {
+        let (canvas_x, canvas_y) = (x + layer_x as i32, y + layer_y as i32);
+        if canvas_x >= 0 && canvas_y >= 0 && canvas_x < width as i32 && canvas_y < height as i32 {
+            blend(
+                canvas.get_pixel_mut(canvas_x as u32, canvas_y as u32),
+                pixel,
+            );
+        }
+    }
+}
+
+/// Blends `top` over `bottom` with source-over compositing, where alpha is the last channel
+/// of pixels with 2 or 4 channels.
+///
+/// Unlike `Pixel::blend`, the channels are rounded, so an opaque `bottom` stays opaque.
+pub fn blend<P>(bottom: &mut P, top: &P)
+where
+    P: image::Pixel<Subpixel = u8>,
+{
+    let color_channels = filter::color_channels::<P>();
+    let alpha = |pixel: &P| match pixel.channels().get(color_channels) {
+        Some(alpha) => f32::from(*alpha) / 255.0,
+        None => 1.0,
+    };
+    let (top_alpha, bottom_alpha) = (alpha(top), alpha(bottom) * (1.0 - alpha(top)));
+    let out_alpha = top_alpha + bottom_alpha;
+    if out_alpha == 0.0 {
+        return;
+    }
+
+    let channels = bottom.channels_mut().iter_mut().zip(top.channels());
+    for (bottom, top) in channels.take(color_channels) {
+        *bottom = ((f32::from(*top) * top_alpha + f32::from(*bottom) * bottom_alpha) / out_alpha)
+            .round() as u8;
+    }
+    if let Some(alpha) = bottom.channels_mut().get_mut(color_channels) {
+        *alpha = (out_alpha * 255.0).round() as u8;
+    }
+}
+
+/// Rotates `layer` clockwise by `degrees` around its center.
+///
+/// The layer is interpolated with premultiplied alpha, so the color of `transparent`
+/// and of transparent pixels does not fringe the edges.
+///
+/// The result is the size of the rotated bounding box, filled with `transparent` outside the layer.
+pub fn rotate<P>(
+    layer: &image::ImageBuffer<P, Vec<u8>>,
+    degrees: f32,
+    transparent: P,
+) -> image::ImageBuffer<P, Vec<u8>>
+where
+    P: image::Pixel<Subpixel = u8> + Send + Sync + 'static,
+{
+    let (width, height) = layer.dimensions();
+    let side = f64::from(width).hypot(f64::from(height)).ceil() as u32 + 2;
+    let mut transparent = transparent;
+    filter::premultiply(&mut transparent);
+    let mut square = image::ImageBuffer::from_pixel(side, side, transparent);
+    composite(
+        &mut square,
+        layer,
+        (((side - width) / 2) as i32, ((side - height) / 2) as i32),
+    );
+    square.pixels_mut().for_each(filter::premultiply);
+
+    let mut rotated = imageproc::geometric_transformations::rotate_about_center(
+        &square,
+        degrees.to_radians(),
+        imageproc::geometric_transformations::Interpolation::Bilinear,
+        transparent,
+    );
+    rotated.pixels_mut().for_each(filter::unpremultiply);
+
+    let size = layout::rotated_size((width as i32, height as i32), degrees);
+    let (width, height) = ((size.0 as u32).min(side), (size.1 as u32).min(side));
+    image::imageops::crop(
+        &mut rotated,
+        (side - width) / 2,
+        (side - height) / 2,
+        width,
+        height,
+    )
+    .to_image()
+}
+
+/// Multiplies the alpha channel, the last one of each pixel, by `opacity`.
+pub fn fade<P>(layer: &mut image::ImageBuffer<P, Vec<u8>>, opacity: f32)
+where
+    P: image::Pixel<Subpixel = u8> + 'static,
+{
+    if opacity >= 1.0 {
+        return;
+    }
+    for pixel in layer.pixels_mut() {
+        if let Some(alpha) = pixel.channels_mut().last_mut() {
+            *alpha = (f32::from(*alpha) * opacity).round() as u8;
+        }
+    }
+}
+
+/// How a layer is repeated across the whole canvas.
+#[derive(Debug, Clone)]
+pub struct Tiling {
+    /// The gaps between tiles as `(horizontal, vertical)`. The tile height is used if `None`.
+    pub spacing: Option<Pair<u32>>,
+    /// Shifts every other row by half a tile.
+    pub stagger: bool,
+    pub opacity: f32,
+}
+
+impl Tiling {
+    /// Repeats `layer` across `canvas` on a grid centered on the canvas.
+    pub fn composite<P>(
+        &self,
+        canvas: &mut image::ImageBuffer<P, Vec<u8>>,
+        layer: &image::ImageBuffer<P, Vec<u8>>,
+    ) where
+        P: image::Pixel<Subpixel = u8> + 'static,
+    {
+        let mut layer = layer.clone();
+        fade(&mut layer, self.opacity);
+
+        let (canvas_width, canvas_height) = (canvas.width() as i32, canvas.height() as i32);
+        let (width, height) = (layer.width() as i32, layer.height() as i32);
+        let (gap_x, gap_y) = self
+            .spacing
+            .map(|s| (s.x as i32, s.y as i32))
+            .unwrap_or((height, height));
+        let (pitch_x, pitch_y) = ((width + gap_x).max(1), (height + gap_y).max(1));
+
+        let start_x = ((canvas_width - width) / 2).rem_euclid(pitch_x) - pitch_x;
+        let start_y = ((canvas_height - height) / 2).rem_euclid(pitch_y) - pitch_y;
+        let first_row = ((canvas_height - height) / 2).div_euclid(pitch_y) + 1;
+
+        for (row, y) in (start_y..canvas_height)
+            .step_by(pitch_y as usize)
+            .enumerate()
+        {
+            let shift = if self.stagger && (row as i32 - first_row) % 2 != 0 {
+                pitch_x / 2
+            } else {
+                0
+            };
+            for x in (start_x + shift - pitch_x..canvas_width).step_by(pitch_x as usize) {
+                if x + width > 0 && y + height > 0 {
+                    composite(canvas, &layer, (x, y));
+                }
+            }
+        }
+    }
+}
+
+/// Composites `layer` at `origin`, or across the whole canvas with `tiling`.
+pub fn stamp<P>(
+    canvas: &mut image::ImageBuffer<P, Vec<u8>>,
+    layer: &image::ImageBuffer<P, Vec<u8>>,
+    origin: (i32, i32),
+    tiling: Option<&Tiling>,
+) where
+    P: image::Pixel<Subpixel = u8> + 'static,
+{
+    match tiling {
+        Some(tiling) => tiling.composite(canvas, layer),
+        None => composite(canvas, layer, origin),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlay() -> Overlay {
+        Overlay {
+            path: PathBuf::from("logo.png"),
+            position: None,
+            gravity: Some(Gravity::RightCentered),
+            scale: None,
+            opacity: 1.0,
+            rotation: None,
+            margin: 10,
+        }
+    }
+
+    #[test]
+    fn test_origin_margin() {
+        assert_eq!(overlay().origin((400, 200), (50, 20)), (340, 90));
+    }
+
+    #[test]
+    fn test_origin_position() {
+        let overlay = Overlay {
+            position: Some(Pair::from((5, 6))),
+            ..overlay()
+        };
+        assert_eq!(overlay.origin((400, 200), (50, 20)), (5, 6));
+    }
+
+    #[test]
+    fn test_composite() {
+        let mut canvas = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 255, 255, 255]));
+        let layer = image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 128]));
+        composite(&mut canvas, &layer, (3, -1));
+
+        assert_eq!(canvas.get_pixel(2, 0)[0], 255);
+        assert!(canvas.get_pixel(3, 0)[0] < 255);
+        assert_eq!(canvas.get_pixel(3, 1)[0], 255);
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut pixel = image::Rgba([255, 255, 255, 255]);
+        blend(&mut pixel, &image::Rgba([0, 0, 0, 127]));
+        assert_eq!(pixel, image::Rgba([128, 128, 128, 255]));
+
+        let mut pixel = image::LumaA([0, 0]);
+        blend(&mut pixel, &image::LumaA([200, 100]));
+        assert_eq!(pixel, image::LumaA([200, 100]));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let layer = image::RgbaImage::from_pixel(40, 10, image::Rgba([0, 0, 0, 255]));
+        let transparent = image::Rgba([0, 0, 0, 0]);
+        assert_eq!(rotate(&layer, 90.0, transparent).dimensions(), (10, 40));
+        assert_eq!(rotate(&layer, 0.0, transparent).dimensions(), (40, 10));
+    }
+
+    #[test]
+    fn test_rotate_without_fringe() {
+        let layer = image::RgbaImage::from_fn(12, 12, |x, _| {
+            if x < 6 {
+                image::Rgba([0, 0, 255, 255])
+            } else {
+                image::Rgba([255, 0, 0, 0])
+            }
+        });
+        let rotated = rotate(&layer, 30.0, image::Rgba([0, 255, 0, 0]));
+        assert!(rotated.pixels().any(|p| p[3] > 0 && p[3] < 255));
+        for pixel in rotated.pixels().filter(|p| p[3] > 0) {
+            assert_eq!((pixel[0], pixel[1]), (0, 0), "{:?}", pixel);
+        }
+        for pixel in rotated.pixels().filter(|p| p[3] >= 64) {
+            assert!(pixel[2] >= 245, "{:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn test_tiling() {
+        let mut canvas = image::RgbaImage::from_pixel(20, 20, image::Rgba([255, 255, 255, 255]));
+        let layer = image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 255]));
+        let tiling = Tiling {
+            spacing: Some(Pair::from((3, 3))),
+            stagger: false,
+            opacity: 1.0,
+        };
+        tiling.composite(&mut canvas, &layer);
+
+        let tiles = canvas.pixels().filter(|p| p[0] == 0).count();
+        assert_eq!(tiles, 4 * 4 * 4);
+        assert_eq!(canvas.get_pixel(9, 9)[0], 0);
+    }
+}
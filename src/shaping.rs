@@ -0,0 +1,107 @@
+use failure::Fail;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Fail)]
+pub enum ShapingError {
+    #[fail(display = "invalid font feature: {}", _0)]
+    InvalidFeature(String),
+}
+
+/// Parses comma separated OpenType features such as `liga,ss01,-kern,salt=2`.
+pub fn parse_features(s: &str) -> Result<Vec<rustybuzz::Feature>, ShapingError> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|f| {
+            f.parse()
+                .map_err(|_| ShapingError::InvalidFeature(f.to_owned()))
+        })
+        .collect()
+}
+
+/// Splits a line into runs by the Unicode bidi algorithm.
+///
+/// Returns byte ranges of `text` in visual order with whether each run is right-to-left.
+pub fn visual_runs(text: &str) -> Vec<(Range<usize>, bool)> {
+    let bidi = unicode_bidi::BidiInfo::new(text, None);
+    bidi.paragraphs
+        .iter()
+        .flat_map(|paragraph| {
+            let (levels, runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+            runs.into_iter()
+                .map(|run| {
+                    let rtl = levels[run.start].is_rtl();
+                    (run, rtl)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// A shaped glyph in font units.
+#[derive(Debug, Clone, Copy)]
+pub struct ShapedGlyph {
+    pub id: u32,
+    pub x_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+/// Shapes a run of a single direction with face `index` of `data`.
+///
+/// The glyphs are returned in visual order, or `None` if the face cannot be parsed.
+pub fn shape(
+    data: &[u8],
+    index: u32,
+    text: &str,
+    rtl: bool,
+    features: &[rustybuzz::Feature],
+) -> Option<Vec<ShapedGlyph>> {
+    let face = rustybuzz::Face::from_slice(data, index)?;
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.set_direction(if rtl {
+        rustybuzz::Direction::RightToLeft
+    } else {
+        rustybuzz::Direction::LeftToRight
+    });
+
+    let glyphs = rustybuzz::shape(&face, features, buffer);
+    let shaped = glyphs
+        .glyph_infos()
+        .iter()
+        .zip(glyphs.glyph_positions())
+        .map(|(info, position)| ShapedGlyph {
+            id: info.codepoint,
+            x_advance: position.x_advance,
+            x_offset: position.x_offset,
+            y_offset: position.y_offset,
+        })
+        .collect();
+    Some(shaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_features() {
+        let features = parse_features("liga, ss01,-kern").unwrap();
+        assert_eq!(features.len(), 3);
+        assert!(parse_features("").unwrap().is_empty());
+        assert!(parse_features("liga,=").is_err());
+    }
+
+    #[test]
+    fn test_visual_runs_ltr() {
+        assert_eq!(visual_runs("abc"), vec![(0..3, false)]);
+    }
+
+    #[test]
+    fn test_visual_runs_mixed() {
+        // "ab " followed by two Hebrew letters (2 bytes each).
+        let runs = visual_runs("ab \u{5d0}\u{5d1}");
+        assert_eq!(runs, vec![(0..3, false), (3..7, true)]);
+    }
+}
@@ -0,0 +1,210 @@
+use chrono::format::{Item, StrftimeItems};
+use failure::Fail;
+use std::collections::HashMap;
+use std::path::Path;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Debug, Clone, Fail)]
+pub enum TemplateError {
+    #[fail(display = "unknown template variable: {{{}}}", _0)]
+    UnknownVariable(String),
+    #[fail(display = "unclosed `{{` in template")]
+    Unclosed,
+    #[fail(display = "invalid date format: {}", _0)]
+    InvalidDateFormat(String),
+}
+
+/// Values substituted for the placeholders of a text.
+#[derive(Debug, Clone)]
+pub struct Variables {
+    pub filename: String,
+    pub width: u32,
+    pub height: u32,
+    pub date: chrono::DateTime<chrono::Local>,
+    /// Fields of the primary image by EXIF tag name such as `DateTimeOriginal`.
+    pub exif: HashMap<String, String>,
+}
+
+impl Variables {
+    /// Collects the variables of an input image of `size`, or of a blank canvas if `path` is `None`.
+    pub fn new<P>(path: Option<P>, size: (u32, u32)) -> Variables
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().map(AsRef::as_ref);
+        Variables {
+            filename: path
+                .and_then(Path::file_name)
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            width: size.0,
+            height: size.1,
+            date: chrono::Local::now(),
+            exif: path.map(read_exif).unwrap_or_default(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<String, TemplateError> {
+        let (name, argument) = match name.find(':') {
+            Some(index) => (&name[..index], Some(&name[index + 1..])),
+            None => (name, None),
+        };
+
+        match (name, argument) {
+            ("filename", None) => Ok(self.filename.clone()),
+            ("width", None) => Ok(self.width.to_string()),
+            ("height", None) => Ok(self.height.to_string()),
+            ("date", format) => {
+                let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+                let items = StrftimeItems::new(format).collect::<Vec<_>>();
+                if items.iter().any(|i| matches!(i, Item::Error)) {
+                    return Err(TemplateError::InvalidDateFormat(format.to_owned()));
+                }
+                Ok(self.date.format_with_items(items.into_iter()).to_string())
+            }
+            (name, None) if name.starts_with("exif.") => {
+                let tag = &name["exif.".len()..];
+                Ok(self.exif.get(tag).cloned().unwrap_or_else(|| {
+                    log::warn!("no EXIF field {} in {}", tag, self.filename);
+                    String::new()
+                }))
+            }
+            _ => Err(TemplateError::UnknownVariable(
+                argument.map_or(name.to_owned(), |a| format!("{}:{}", name, a)),
+            )),
+        }
+    }
+}
+
+fn read_exif(path: &Path) -> HashMap<String, String> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return HashMap::new(),
+    };
+
+    match exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file)) {
+        Ok(exif) => exif_fields(&exif),
+        Err(error) => {
+            log::debug!("no EXIF data in {:?}: {}", path, error);
+            HashMap::new()
+        }
+    }
+}
+
+/// Returns the fields of the primary image, with ASCII strings as they are rather than quoted.
+fn exif_fields(exif: &exif::Exif) -> HashMap<String, String> {
+    exif.fields()
+        .filter(|f| f.ifd_num == exif::In::PRIMARY)
+        .map(|f| {
+            let value = match &f.value {
+                exif::Value::Ascii(strings) => strings
+                    .iter()
+                    .map(|s| String::from_utf8_lossy(s).trim_end_matches('\0').to_owned())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                _ => f.display_value().to_string(),
+            };
+            (f.tag.to_string(), value)
+        })
+        .collect()
+}
+
+/// Replaces `{name}` placeholders in `template` with `variables`.
+///
+/// Supported names are `filename`, `width`, `height`, `date` (optionally with
+/// a strftime format as `date:%Y-%m-%d`) and `exif.TAG`. `{{` and `}}` are literal braces.
+pub fn expand(template: &str, variables: &Variables) -> Result<String, TemplateError> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                expanded.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                expanded.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(TemplateError::Unclosed),
+                    }
+                }
+                expanded.push_str(&variables.get(&name)?);
+            }
+            c => expanded.push(c),
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A big endian TIFF structure with `Model` set to `X100` and `XResolution` set to 72.
+    fn exif() -> exif::Exif {
+        let mut tiff = b"MM\0*\0\0\0\x08\0\x02".to_vec();
+        tiff.extend_from_slice(&[0x01, 0x10, 0, 2, 0, 0, 0, 5, 0, 0, 0, 38]);
+        tiff.extend_from_slice(&[0x01, 0x1a, 0, 5, 0, 0, 0, 1, 0, 0, 0, 43]);
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        tiff.extend_from_slice(b"X100\0");
+        tiff.extend_from_slice(&[0, 0, 0, 72, 0, 0, 0, 1]);
+        exif::Reader::new().read_raw(tiff).unwrap()
+    }
+
+    fn variables() -> Variables {
+        Variables {
+            filename: "photo.jpg".to_owned(),
+            width: 640,
+            height: 480,
+            date: chrono::Local
+                .from_local_datetime(
+                    &chrono::NaiveDate::from_ymd_opt(2019, 12, 24)
+                        .and_then(|d| d.and_hms_opt(18, 30, 0))
+                        .unwrap(),
+                )
+                .unwrap(),
+            exif: exif_fields(&exif()),
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let expanded = expand("{filename} {width}x{height}", &variables()).unwrap();
+        assert_eq!(expanded, "photo.jpg 640x480");
+    }
+
+    #[test]
+    fn test_expand_date() {
+        let variables = variables();
+        assert_eq!(expand("{date}", &variables).unwrap(), "2019-12-24");
+        assert_eq!(expand("{date:%H:%M}", &variables).unwrap(), "18:30");
+        assert!(expand("{date:%Q}", &variables).is_err());
+    }
+
+    #[test]
+    fn test_expand_exif() {
+        let variables = variables();
+        assert_eq!(expand("{exif.Model}", &variables).unwrap(), "X100");
+        assert_eq!(expand("{exif.XResolution}", &variables).unwrap(), "72");
+        assert_eq!(expand("{exif.DateTimeOriginal}", &variables).unwrap(), "");
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        let variables = variables();
+        assert_eq!(expand("{{width}}", &variables).unwrap(), "{width}");
+        assert!(expand("{size}", &variables).is_err());
+        assert!(expand("{width", &variables).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+/// Returns the vertical presentation form (U+FE10-FE19, U+FE30-FE4F) of a punctuation mark.
+pub fn vertical_form(c: char) -> Option<char> {
+    let form = match c {
+        '，' => '\u{fe10}',
+        '、' => '\u{fe11}',
+        '。' => '\u{fe12}',
+        '：' => '\u{fe13}',
+        '；' => '\u{fe14}',
+        '！' => '\u{fe15}',
+        '？' => '\u{fe16}',
+        '〖' => '\u{fe17}',
+        '〗' => '\u{fe18}',
+        '…' => '\u{fe19}',
+        '‥' => '\u{fe30}',
+        '—' => '\u{fe31}',
+        '–' => '\u{fe32}',
+        '＿' => '\u{fe33}',
+        '（' => '\u{fe35}',
+        '）' => '\u{fe36}',
+        '｛' => '\u{fe37}',
+        '｝' => '\u{fe38}',
+        '〔' => '\u{fe39}',
+        '〕' => '\u{fe3a}',
+        '【' => '\u{fe3b}',
+        '】' => '\u{fe3c}',
+        '《' => '\u{fe3d}',
+        '》' => '\u{fe3e}',
+        '〈' => '\u{fe3f}',
+        '〉' => '\u{fe40}',
+        '「' => '\u{fe41}',
+        '」' => '\u{fe42}',
+        '『' => '\u{fe43}',
+        '』' => '\u{fe44}',
+        '［' => '\u{fe47}',
+        '］' => '\u{fe48}',
+        _ => return None,
+    };
+    Some(form)
+}
+
+/// Whether a character is turned sideways in vertical text when it has no vertical form.
+pub fn is_sideways(c: char) -> bool {
+    matches!(
+        c,
+        'ー' | '～'
+            | '〜'
+            | '—'
+            | '–'
+            | '―'
+            | '…'
+            | '‥'
+            | '－'
+            | '＝'
+            | '（'
+            | '）'
+            | '「'
+            | '」'
+            | '『'
+            | '』'
+            | '［'
+            | '］'
+            | '｛'
+            | '｝'
+            | '〔'
+            | '〕'
+            | '【'
+            | '】'
+            | '〈'
+            | '〉'
+            | '《'
+            | '》'
+            | '〖'
+            | '〗'
+            | '('
+            | ')'
+            | '['
+            | ']'
+            | '{'
+            | '}'
+            | '<'
+            | '>'
+            | '-'
+            | '='
+            | '~'
+    )
+}
+
+/// Whether a character is a small kana, which moves to the upper right of its cell in vertical text.
+pub fn is_small_kana(c: char) -> bool {
+    matches!(
+        c,
+        'ぁ' | 'ぃ'
+            | 'ぅ'
+            | 'ぇ'
+            | 'ぉ'
+            | 'っ'
+            | 'ゃ'
+            | 'ゅ'
+            | 'ょ'
+            | 'ゎ'
+            | 'ゕ'
+            | 'ゖ'
+            | 'ァ'
+            | 'ィ'
+            | 'ゥ'
+            | 'ェ'
+            | 'ォ'
+            | 'ッ'
+            | 'ャ'
+            | 'ュ'
+            | 'ョ'
+            | 'ヮ'
+            | 'ヵ'
+            | 'ヶ'
+            | 'ㇰ'..='ㇿ'
+    )
+}
+
+/// Whether a punctuation mark sits in the lower left of its cell in horizontal text
+/// and moves to the upper right in vertical text when it has no vertical form.
+pub fn is_corner_punctuation(c: char) -> bool {
+    matches!(c, '、' | '。' | '，' | '．' | ',' | '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vertical_form() {
+        assert_eq!(vertical_form('、'), Some('︑'));
+        assert_eq!(vertical_form('「'), Some('﹁'));
+        assert_eq!(vertical_form('あ'), None);
+    }
+
+    #[test]
+    fn test_is_sideways() {
+        assert!(is_sideways('ー'));
+        assert!(is_sideways('（'));
+        assert!(!is_sideways('あ'));
+        assert!(!is_sideways('A'));
+    }
+
+    #[test]
+    fn test_is_small_kana() {
+        assert!(is_small_kana('っ'));
+        assert!(is_small_kana('ャ'));
+        assert!(!is_small_kana('つ'));
+    }
+}